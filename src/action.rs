@@ -0,0 +1,162 @@
+use crate::{channel, command::Operation, config::Config, schema};
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::{fmt, str::FromStr, time::Duration};
+
+/// What performing an operation involves.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Action {
+    Help,
+    /// Send a command to the station
+    Station(schema::Command),
+    /// Send a fixed payload, to the command topic unless another topic is given
+    Custom {
+        topic: Option<String>,
+        payload: Vec<u8>,
+    },
+    /// Perform a sequence of operations
+    Macro(Vec<Step>),
+}
+
+/// Looks up what an operation does in the configuration.
+pub(crate) fn resolve(config: &Config, op: &Operation) -> Result<Action> {
+    if let Operation::Named(name) = op {
+        if let Some(m) = config.macros.iter().find(|m| m.name == *name) {
+            return Ok(Action::Macro(m.steps.clone()));
+        }
+        if let Some(o) = config.operations.iter().find(|o| o.name == *name) {
+            // Other topics are not necessarily read by the station, so are sent JSON
+            let payload = match o.topic {
+                Some(_) => serde_json::to_vec(&o.payload)?,
+                None => config.station.encoding.encode(&o.payload)?,
+            };
+            return Ok(Action::Custom {
+                topic: o.topic.clone(),
+                payload,
+            });
+        }
+    }
+
+    Ok(match channel::station_command(&config.channels, op)? {
+        Some(cmd) => Action::Station(cmd),
+        None => Action::Help,
+    })
+}
+
+/// A step of a macro.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String")]
+pub(crate) enum Step {
+    Run(Operation),
+    Wait(Duration),
+}
+
+impl FromStr for Step {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        match s.strip_prefix("wait ") {
+            Some(duration) => {
+                let duration = duration.trim();
+                let (value, unit) = match duration.find(|c: char| !c.is_ascii_digit()) {
+                    Some(i) => duration.split_at(i),
+                    None => (duration, "s"),
+                };
+                let value: u64 = value
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid wait \"{}\"", duration))?;
+                let secs = match unit.trim() {
+                    "s" => value,
+                    "m" => value * 60,
+                    _ => return Err(anyhow!("Invalid wait \"{}\", use e.g. 5s or 2m", duration)),
+                };
+                Ok(Self::Wait(Duration::from_secs(secs)))
+            }
+            None => Ok(Self::Run(s.parse()?)),
+        }
+    }
+}
+
+impl TryFrom<String> for Step {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Run(op) => write!(f, "{}", op),
+            Self::Wait(d) => write!(f, "wait {}s", d.as_secs()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+            [matrix]
+            username = "@bot:example.com"
+            password = "hunter2"
+            storage = "/var/lib/bot"
+
+            [station]
+            name = "mb7pmf"
+            status_topic = "mb7pmf"
+            command_topic = "mb7pmf/command"
+
+            [[operations]]
+            name = "beacon start"
+            payload = { mode = "cw" }
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn resolve_operations() {
+        let config = config();
+        assert_eq!(resolve(&config, &Operation::Help).unwrap(), Action::Help);
+        assert!(matches!(
+            resolve(&config, &"power off".parse().unwrap()).unwrap(),
+            Action::Station(_)
+        ));
+        assert!(matches!(
+            resolve(&config, &"beacon start".parse().unwrap()).unwrap(),
+            Action::Custom { topic: None, .. }
+        ));
+        assert!(resolve(&config, &"halp".parse().unwrap()).is_err());
+        assert!(resolve(&config, &"power sideways".parse().unwrap()).is_err());
+        assert!(resolve(&config, &"beacon stop".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn parse_steps() {
+        assert_eq!(
+            "PTT disable".parse::<Step>().unwrap(),
+            Step::Run(Operation::Named("ptt disable".to_string()))
+        );
+        assert_eq!(
+            "wait 5s".parse::<Step>().unwrap(),
+            Step::Wait(Duration::from_secs(5))
+        );
+        assert_eq!(
+            "wait 2m".parse::<Step>().unwrap(),
+            Step::Wait(Duration::from_secs(120))
+        );
+        assert_eq!(
+            "wait 10".parse::<Step>().unwrap(),
+            Step::Wait(Duration::from_secs(10))
+        );
+        assert!("wait a bit".parse::<Step>().is_err());
+        assert!("wait 5h".parse::<Step>().is_err());
+        assert_eq!("wait 2m".parse::<Step>().unwrap().to_string(), "wait 120s");
+    }
+}
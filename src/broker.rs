@@ -0,0 +1,125 @@
+use crate::config::MqttConfig;
+use anyhow::{Context, Result};
+use mqtt_channel_client as mqtt;
+use std::time::Duration;
+
+pub(crate) fn create_client(
+    config: &MqttConfig,
+    broker: &str,
+    client_id: &str,
+) -> Result<mqtt::Client> {
+    Ok(mqtt::Client::new(
+        mqtt::paho_mqtt::create_options::CreateOptionsBuilder::new()
+            .server_uri(broker)
+            .client_id(client_id)
+            .persistence(mqtt::paho_mqtt::PersistenceType::None)
+            .mqtt_version(mqtt_version(config))
+            .finalize(),
+        mqtt::ClientConfig::default(),
+    )?)
+}
+
+pub(crate) fn connect_options(
+    config: &MqttConfig,
+    broker: &str,
+) -> Result<mqtt::paho_mqtt::ConnectOptions> {
+    let mut options = if config.version == 5 {
+        let mut options = mqtt::paho_mqtt::connect_options::ConnectOptionsBuilder::new_v5();
+        options.clean_start(true);
+        options
+    } else {
+        let mut options = mqtt::paho_mqtt::connect_options::ConnectOptionsBuilder::new();
+        options.clean_session(true);
+        options
+    };
+    options
+        .automatic_reconnect(Duration::from_secs(1), Duration::from_secs(5))
+        .keep_alive_interval(Duration::from_secs(5))
+        .user_name(&config.username)
+        .password(config.password());
+
+    if let Some(topic) = &config.availability_topic {
+        options.will_message(availability_message(topic, false));
+    }
+
+    if config.uses_tls(broker) {
+        let mut ssl = mqtt::paho_mqtt::SslOptionsBuilder::new();
+        ssl.enable_server_cert_auth(true);
+        if let Some(tls) = &config.tls {
+            if let Some(path) = &tls.ca_file {
+                ssl.trust_store(path)
+                    .with_context(|| format!("Invalid MQTT CA file {}", path.display()))?;
+            }
+            if let Some(path) = &tls.cert_file {
+                ssl.key_store(path).with_context(|| {
+                    format!("Invalid MQTT client certificate {}", path.display())
+                })?;
+            }
+            if let Some(path) = &tls.key_file {
+                ssl.private_key(path)
+                    .with_context(|| format!("Invalid MQTT client key {}", path.display()))?;
+            }
+            ssl.verify(tls.verify_hostname);
+        } else {
+            ssl.verify(true);
+        }
+        options.ssl_options(ssl.finalize());
+    }
+
+    Ok(options.finalize())
+}
+
+fn mqtt_version(config: &MqttConfig) -> u32 {
+    match config.version {
+        5 => mqtt::paho_mqtt::MQTT_VERSION_5,
+        _ => mqtt::paho_mqtt::MQTT_VERSION_3_1_1,
+    }
+}
+
+/// Builds a (retained) message stating whether the bot is available.
+pub(crate) fn availability_message(topic: &str, online: bool) -> mqtt::paho_mqtt::Message {
+    mqtt::paho_mqtt::Message::new_retained(topic, if online { "online" } else { "offline" }, 1)
+}
+
+/// Builds a command message, asking for a reply on `response_topic` tagged with `correlation_id`
+/// when using MQTT v5.
+pub(crate) fn command_message(
+    topic: &str,
+    payload: Vec<u8>,
+    reply: Option<(&str, &str)>,
+) -> Result<mqtt::paho_mqtt::Message> {
+    let mut message = mqtt::paho_mqtt::MessageBuilder::new()
+        .topic(topic)
+        .payload(payload)
+        .qos(2);
+
+    if let Some((response_topic, correlation_id)) = reply {
+        let mut properties = mqtt::paho_mqtt::Properties::new();
+        properties.push_string(mqtt::paho_mqtt::PropertyCode::ResponseTopic, response_topic)?;
+        properties.push_binary(
+            mqtt::paho_mqtt::PropertyCode::CorrelationData,
+            correlation_id.as_bytes(),
+        )?;
+        message = message.properties(properties);
+    }
+
+    Ok(message.finalize())
+}
+
+/// Starts a client, explaining the likely cause if the connection could not be established.
+pub(crate) async fn start(client: &mqtt::Client, config: &MqttConfig, broker: &str) -> Result<()> {
+    let result = client.start(connect_options(config, broker)?).await;
+
+    if config.uses_tls(broker) {
+        result.with_context(|| {
+            format!(
+                "Failed to connect to MQTT broker {} using TLS, check that the CA file trusts the \
+                 broker's certificate, the certificate matches the broker hostname and the client \
+                 certificate/key are accepted by the broker",
+                broker
+            )
+        })
+    } else {
+        result.with_context(|| format!("Failed to connect to MQTT broker {}", broker))
+    }
+}
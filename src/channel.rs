@@ -0,0 +1,271 @@
+use crate::{
+    command::Operation,
+    config::{ChannelConfig, STATUS_TEMPLATE_PLACEHOLDERS},
+    schema::{Command, Status},
+};
+use anyhow::{anyhow, Result};
+
+/// Shown for status fields the station has not reported.
+const UNKNOWN: &str = "unknown";
+
+fn find<'a>(channels: &'a [ChannelConfig], name: &str) -> Option<&'a ChannelConfig> {
+    channels.iter().find(|c| c.name == name)
+}
+
+/// The command message to send to the station to perform an operation, if any.
+pub(crate) fn station_command(
+    channels: &[ChannelConfig],
+    op: &Operation,
+) -> Result<Option<Command>> {
+    let mut cmd = Command {
+        version: None,
+        fields: channels
+            .iter()
+            .map(|c| (c.command_field.clone(), None))
+            .collect(),
+    };
+
+    match op {
+        Operation::Help => return Ok(None),
+        Operation::Shutdown => {
+            for c in channels.iter().filter(|c| c.shutdown) {
+                cmd.fields.insert(c.command_field.clone(), Some(false));
+            }
+        }
+        Operation::Named(name) => {
+            let (c, verb) = name
+                .rsplit_once(' ')
+                .and_then(|(channel, verb)| Some((find(channels, channel)?, verb)))
+                .ok_or_else(|| anyhow!("Unknown command \"{}\"", name))?;
+            let value = if verb == c.enable_word {
+                true
+            } else if verb == c.disable_word {
+                false
+            } else {
+                return Err(anyhow!(
+                    "Channel \"{}\" can only be switched {} or {}",
+                    c.name,
+                    c.enable_word,
+                    c.disable_word
+                ));
+            };
+            cmd.fields.insert(c.command_field.clone(), Some(value));
+        }
+    }
+
+    Ok(Some(cmd))
+}
+
+/// Checks if a reported status is the state a command is intended to result in.
+pub(crate) fn is_satisfied_by(channels: &[ChannelConfig], cmd: &Command, status: &Status) -> bool {
+    cmd.values().all(|(field, value)| {
+        channels
+            .iter()
+            .find(|c| c.command_field == field)
+            .is_some_and(|c| status.get(&c.status_field) == Some(value))
+    })
+}
+
+/// Whether any channel's status fields differ between two status reports.
+pub(crate) fn state_changed(channels: &[ChannelConfig], old: &Status, new: &Status) -> bool {
+    channels.iter().any(|c| {
+        [Some(&c.status_field), c.active_field.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|field| old.0.get(field) != new.0.get(field))
+    })
+}
+
+/// Names that can be used in the status template.
+pub(crate) fn status_placeholders(channels: &[ChannelConfig]) -> Vec<&str> {
+    let mut names = STATUS_TEMPLATE_PLACEHOLDERS.to_vec();
+    for c in channels {
+        names.push(&c.status_field);
+        names.extend(c.active_field.as_deref());
+    }
+    names
+}
+
+fn format_field(value: Option<bool>, labels: &[String; 2]) -> &str {
+    match value {
+        Some(true) => &labels[0],
+        Some(false) => &labels[1],
+        None => UNKNOWN,
+    }
+}
+
+/// Formatted values of each channel's status fields, keyed by field name.
+pub(crate) fn status_values<'a>(
+    channels: &'a [ChannelConfig],
+    status: &Status,
+) -> Vec<(&'a str, &'a str)> {
+    let mut values = Vec::new();
+    for c in channels {
+        values.push((
+            c.status_field.as_str(),
+            format_field(status.get(&c.status_field), &c.enabled_labels),
+        ));
+        if let Some(field) = &c.active_field {
+            values.push((
+                field.as_str(),
+                format_field(status.get(field), &c.active_labels),
+            ));
+        }
+    }
+    values
+}
+
+/// A line per channel summarising its state, e.g. `TX Power: [ENABLED] [ON]`.
+pub(crate) fn status_summary(channels: &[ChannelConfig], status: &Status) -> String {
+    channels
+        .iter()
+        .map(|c| {
+            let mut line = format!(
+                "{}: [{}]",
+                c.label,
+                format_field(status.get(&c.status_field), &c.enabled_labels)
+            );
+            if let Some(field) = &c.active_field {
+                line.push_str(&format!(
+                    " [{}]",
+                    format_field(status.get(field), &c.active_labels)
+                ));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("<br>\n")
+}
+
+/// Describes how the state of each channel differs between two status reports.
+pub(crate) fn describe_changes(
+    channels: &[ChannelConfig],
+    old: &Status,
+    new: &Status,
+) -> Vec<String> {
+    let mut changes = Vec::new();
+
+    for c in channels {
+        let enabled = new.get(&c.status_field);
+        if old.get(&c.status_field) != enabled {
+            match enabled {
+                Some(true) => changes.push(format!("{} was enabled", c.label)),
+                Some(false) => changes.push(format!("{} was disabled", c.label)),
+                None => {}
+            }
+        }
+
+        if let Some(field) = &c.active_field {
+            let active = new.get(field);
+            if old.get(field) != active && active.is_some() {
+                changes.push(format!(
+                    "{} changed to {}",
+                    c.label,
+                    format_field(active, &c.active_labels)
+                ));
+            }
+        }
+    }
+
+    changes
+}
+
+/// The commands available for the configured channels, e.g. `power on, power off`.
+pub(crate) fn commands(channels: &[ChannelConfig]) -> Vec<String> {
+    channels
+        .iter()
+        .flat_map(|c| {
+            [
+                format!("{} {}", c.name, c.enable_word),
+                format!("{} {}", c.name, c.disable_word),
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::default_channels as channels;
+
+    fn status(fields: &[(&str, bool)]) -> Status {
+        let mut status = Status::default();
+        for (field, value) in fields {
+            status.set(field, *value);
+        }
+        status
+    }
+
+    #[test]
+    fn commands_for_operations() {
+        let channels = channels();
+
+        let cmd = station_command(&channels, &"power off".parse().unwrap())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cmd.values().collect::<Vec<_>>(),
+            [("enable_tx_power", false)]
+        );
+        assert_eq!(
+            serde_json::to_string(&cmd).unwrap(),
+            r#"{"enable_ptt":null,"enable_tx_power":false}"#
+        );
+
+        let cmd = station_command(&channels, &Operation::Shutdown)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cmd.values().count(), 2);
+
+        assert_eq!(station_command(&channels, &Operation::Help).unwrap(), None);
+        assert!(station_command(&channels, &"ptt on".parse().unwrap()).is_err());
+        assert!(station_command(&channels, &"amp on".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn satisfied_by() {
+        let channels = channels();
+        let cmd = station_command(&channels, &"power off".parse().unwrap())
+            .unwrap()
+            .unwrap();
+
+        assert!(is_satisfied_by(
+            &channels,
+            &cmd,
+            &status(&[("tx_power_enabled", false), ("ptt_enabled", true)])
+        ));
+        assert!(!is_satisfied_by(
+            &channels,
+            &cmd,
+            &status(&[("tx_power_enabled", true)])
+        ));
+        assert!(!is_satisfied_by(&channels, &cmd, &Status::default()));
+    }
+
+    #[test]
+    fn summarises_status() {
+        let channels = channels();
+        let status = status(&[
+            ("tx_power_enabled", true),
+            ("tx_power_active", true),
+            ("ptt_enabled", false),
+        ]);
+
+        assert_eq!(
+            status_summary(&channels, &status),
+            "TX Power: [ENABLED] [ON]<br>\nPTT: [DISABLED] [unknown]"
+        );
+        assert!(state_changed(&channels, &Status::default(), &status));
+        let mut other = status.clone();
+        other.0.insert("temperature".to_string(), 21.into());
+        assert!(!state_changed(&channels, &status, &other));
+        assert_eq!(
+            describe_changes(&channels, &Status::default(), &status),
+            [
+                "TX Power was enabled",
+                "TX Power changed to ON",
+                "PTT was disabled"
+            ]
+        );
+    }
+}
@@ -1,5 +1,8 @@
 use anyhow::{anyhow, Error};
-use kagiyama::prometheus::{self as prometheus_client, encoding::EncodeLabelValue};
+use kagiyama::prometheus::encoding::{EncodeLabelValue, LabelValueEncoder};
+use matrix_sdk::ruma::UserId;
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
 
 #[derive(Clone, Debug, PartialEq)]
 pub(crate) struct Command {
@@ -35,14 +38,153 @@ impl TryFrom<String> for Command {
     }
 }
 
-#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelValue)]
+/// Extracts the text of a command from a Matrix message.
+///
+/// Reply fallbacks and code are ignored. A message that is addressed to the bot (via a mention
+/// pill or a leading `@bot:`) does not need the `!station` marker, it is implied.
+/// Returns `None` if the message does not look like a command.
+pub(crate) fn extract_command_text(
+    body: &str,
+    formatted_body: Option<&str>,
+    bot_user: &UserId,
+    station_name: &str,
+) -> Option<String> {
+    let (text, mentioned) = match formatted_body {
+        Some(html) => strip_formatted_body(html, bot_user),
+        None => (strip_plain_body(body), false),
+    };
+
+    let (text, mentioned) = match strip_mention(text.trim(), bot_user) {
+        Some(text) => (text, true),
+        None => (text.trim(), mentioned),
+    };
+
+    if text.starts_with('!') {
+        Some(text.to_string())
+    } else if mentioned && !text.is_empty() {
+        Some(format!("!{} {}", station_name, text))
+    } else {
+        None
+    }
+}
+
+fn strip_plain_body(body: &str) -> String {
+    let mut lines = body.lines().peekable();
+
+    // Reply fallback: quoted lines followed by a single blank line
+    if lines.peek().is_some_and(|l| l.starts_with('>')) {
+        while lines.next_if(|l| l.starts_with('>')).is_some() {}
+        lines.next_if(|l| l.trim().is_empty());
+    }
+
+    let mut in_code_block = false;
+    lines
+        .filter(|l| {
+            if l.trim_start().starts_with("```") {
+                in_code_block = !in_code_block;
+                false
+            } else {
+                !in_code_block
+            }
+        })
+        .map(|l| l.split('`').step_by(2).collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Strips a leading plain text mention of the bot (e.g. `@bot:example.com ` or `bot: `).
+///
+/// The bare localpart needs a following `:` or `,`, otherwise ordinary sentences that start with
+/// it (e.g. "closedown is flaky today") would be taken as commands.
+fn strip_mention<'a>(text: &'a str, bot_user: &UserId) -> Option<&'a str> {
+    [
+        (bot_user.as_str().to_string(), true),
+        (format!("@{}", bot_user.localpart()), true),
+        (bot_user.localpart().to_string(), false),
+    ]
+    .iter()
+    .find_map(|(m, whitespace_separates)| {
+        let rest = text.get(m.len()..)?;
+        let addressed = text[..m.len()].eq_ignore_ascii_case(m)
+            && ((*whitespace_separates && rest.starts_with(char::is_whitespace))
+                || rest.starts_with(": ")
+                || rest.starts_with(", "));
+        addressed
+            .then(|| rest.trim_start_matches(|c: char| c == ':' || c == ',' || c.is_whitespace()))
+    })
+}
+
+fn strip_formatted_body(html: &str, bot_user: &UserId) -> (String, bool) {
+    let html = remove_elements(html, "mx-reply");
+    let html = remove_elements(&html, "pre");
+    let html = remove_elements(&html, "code");
+    let html = html.trim_start();
+
+    let mut mentioned = false;
+    let mut html = html;
+    if html.starts_with("<a ") {
+        if let (Some(open_end), Some(close)) = (html.find('>'), html.find("</a>")) {
+            let href = html[..open_end]
+                .replace("%40", "@")
+                .replace("%3A", ":")
+                .replace("%3a", ":");
+            if href.contains(&format!("/#/{}", bot_user)) {
+                mentioned = true;
+                html = html[close + "</a>".len()..]
+                    .trim_start_matches(|c: char| c == ':' || c == ',' || c.is_whitespace());
+            }
+        }
+    }
+
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                text.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+
+    let text = text
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    (text, mentioned)
+}
+
+/// Removes all elements with a given tag name (and their content) from some HTML.
+fn remove_elements(html: &str, tag: &str) -> String {
+    let open = format!("<{}", tag);
+    let close = format!("</{}>", tag);
+
+    let mut html = html.to_string();
+    while let Some(start) = html.find(&open) {
+        match html[start..].find(&close) {
+            Some(end) => html.replace_range(start..start + end + close.len(), " "),
+            None => html.truncate(start),
+        }
+    }
+    html
+}
+
+/// An operation requested of the bot, named operations are looked up in the configuration when
+/// they are performed.
+#[derive(Debug, Clone, Eq, Hash, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
 pub(crate) enum Operation {
     Help,
     Shutdown,
-    PowerOn,
-    PowerOff,
-    PttEnable,
-    PttDisable,
+    /// A channel switch (e.g. `power on`), custom operation or macro
+    Named(String),
 }
 
 impl TryFrom<&[&str]> for Operation {
@@ -52,15 +194,94 @@ impl TryFrom<&[&str]> for Operation {
         match parts {
             ["help"] => Ok(Self::Help),
             ["shutdown"] => Ok(Self::Shutdown),
-            ["power", "on"] => Ok(Self::PowerOn),
-            ["power", "off"] => Ok(Self::PowerOff),
-            ["ptt", "enable"] => Ok(Self::PttEnable),
-            ["ptt", "disable"] => Ok(Self::PttDisable),
-            _ => Err(anyhow!("Unknown command")),
+            [] => Err(anyhow!("Unknown command")),
+            parts if parts.iter().any(|p| p.trim().is_empty()) => {
+                Err(anyhow!("Unknown command \"{}\"", parts.join(" ")))
+            }
+            parts => Ok(Self::Named(parts.join(" "))),
         }
     }
 }
 
+impl FromStr for Operation {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.to_lowercase();
+        let parts: Vec<&str> = s.split(' ').filter(|s| !s.is_empty()).collect();
+        parts[..].try_into()
+    }
+}
+
+impl TryFrom<String> for Operation {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Help => f.write_str("help"),
+            Self::Shutdown => f.write_str("shutdown"),
+            Self::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+impl EncodeLabelValue for Operation {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> fmt::Result {
+        use std::fmt::Write;
+        encoder.write_str(&self.to_string())
+    }
+}
+
+/// An operation that is requested by reacting to a status message with a given emoji.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct ReactionCommand {
+    pub key: String,
+    pub op: Operation,
+}
+
+impl ReactionCommand {
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        // Ignore emoji presentation selectors, clients are inconsistent in sending them
+        let strip = |s: &str| s.replace('\u{fe0f}', "");
+        strip(&self.key) == strip(key)
+    }
+}
+
+impl FromStr for ReactionCommand {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, op) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Reaction command must be in the form EMOJI=COMMAND"))?;
+        Ok(Self {
+            key: key.trim().to_string(),
+            op: op.parse()?,
+        })
+    }
+}
+
+impl TryFrom<String> for ReactionCommand {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for ReactionCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.op)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -71,7 +292,7 @@ mod tests {
             Command::try_from("!mb7pmf power on".to_string()).unwrap(),
             Command {
                 station_name: "mb7pmf".to_string(),
-                op: Operation::PowerOn,
+                op: Operation::Named("power on".to_string()),
             }
         );
     }
@@ -82,7 +303,7 @@ mod tests {
             Command::try_from(" !mb7pmf   power  on ".to_string()).unwrap(),
             Command {
                 station_name: "mb7pmf".to_string(),
-                op: Operation::PowerOn,
+                op: Operation::Named("power on".to_string()),
             }
         );
     }
@@ -93,7 +314,7 @@ mod tests {
             Command::try_from("!MB7PMF Power ON".to_string()).unwrap(),
             Command {
                 station_name: "mb7pmf".to_string(),
-                op: Operation::PowerOn,
+                op: Operation::Named("power on".to_string()),
             }
         );
     }
@@ -103,6 +324,111 @@ mod tests {
         assert!(Command::try_from("mb7pmf power on".to_string()).is_err());
     }
 
+    fn bot() -> &'static UserId {
+        <&UserId>::try_from("@closedown:example.com").unwrap()
+    }
+
+    #[test]
+    fn extract_command_text_plain() {
+        assert_eq!(
+            extract_command_text("!mb7pmf power on", None, bot(), "mb7pmf"),
+            Some("!mb7pmf power on".to_string())
+        );
+        assert_eq!(
+            extract_command_text("hello there", None, bot(), "mb7pmf"),
+            None
+        );
+    }
+
+    #[test]
+    fn extract_command_text_reply_fallback() {
+        assert_eq!(
+            extract_command_text(
+                "> <@closedown:example.com> **mb7pmf** at 2024-01-01\n> TX Power: [ENABLED] [ON]\n\n!mb7pmf shutdown",
+                None,
+                bot(),
+                "mb7pmf"
+            ),
+            Some("!mb7pmf shutdown".to_string())
+        );
+        assert_eq!(
+            extract_command_text(
+                "> <@someone:example.com> !mb7pmf shutdown\n\nshould we?",
+                None,
+                bot(),
+                "mb7pmf"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn extract_command_text_mention() {
+        assert_eq!(
+            extract_command_text("@closedown:example.com: power off", None, bot(), "mb7pmf"),
+            Some("!mb7pmf power off".to_string())
+        );
+        assert_eq!(
+            extract_command_text("closedown: !mb7pmf power off", None, bot(), "mb7pmf"),
+            Some("!mb7pmf power off".to_string())
+        );
+        assert_eq!(
+            extract_command_text("closedownbot: power off", None, bot(), "mb7pmf"),
+            None
+        );
+        assert_eq!(
+            extract_command_text("@closedown power off", None, bot(), "mb7pmf"),
+            Some("!mb7pmf power off".to_string())
+        );
+        assert_eq!(
+            extract_command_text("closedown is flaky today", None, bot(), "mb7pmf"),
+            None
+        );
+    }
+
+    #[test]
+    fn extract_command_text_code() {
+        assert_eq!(
+            extract_command_text("`!mb7pmf shutdown` is the command", None, bot(), "mb7pmf"),
+            None
+        );
+        assert_eq!(
+            extract_command_text("```\n!mb7pmf shutdown\n```", None, bot(), "mb7pmf"),
+            None
+        );
+    }
+
+    #[test]
+    fn extract_command_text_formatted() {
+        assert_eq!(
+            extract_command_text(
+                "Closedown Bot: ptt disable",
+                Some("<a href=\"https://matrix.to/#/@closedown:example.com\">Closedown Bot</a>: ptt disable"),
+                bot(),
+                "mb7pmf"
+            ),
+            Some("!mb7pmf ptt disable".to_string())
+        );
+        assert_eq!(
+            extract_command_text(
+                "> <@closedown:example.com> status\n\n!mb7pmf help",
+                Some("<mx-reply><blockquote>status</blockquote></mx-reply>!mb7pmf help"),
+                bot(),
+                "mb7pmf"
+            ),
+            Some("!mb7pmf help".to_string())
+        );
+        assert_eq!(
+            extract_command_text(
+                "!mb7pmf shutdown",
+                Some("<pre><code>!mb7pmf shutdown\n</code></pre>"),
+                bot(),
+                "mb7pmf"
+            ),
+            None
+        );
+    }
+
     #[test]
     fn parse_operation_ok() {
         assert_eq!(Operation::try_from(&["help"][..]).unwrap(), Operation::Help);
@@ -112,14 +438,47 @@ mod tests {
         );
         assert_eq!(
             Operation::try_from(&["power", "on"][..]).unwrap(),
-            Operation::PowerOn
+            Operation::Named("power on".to_string())
         );
     }
 
+    #[test]
+    fn parse_operation_from_str() {
+        assert_eq!(
+            "PTT  Enable".parse::<Operation>().unwrap(),
+            Operation::Named("ptt enable".to_string())
+        );
+        for op in ["help", "shutdown", "power on", "linear amp off"] {
+            assert_eq!(op.parse::<Operation>().unwrap().to_string(), op);
+        }
+    }
+
+    #[test]
+    fn parse_reaction_command() {
+        assert_eq!(
+            "🔇=ptt disable".parse::<ReactionCommand>().unwrap(),
+            ReactionCommand {
+                key: "🔇".to_string(),
+                op: Operation::Named("ptt disable".to_string()),
+            }
+        );
+        assert!("🔇".parse::<ReactionCommand>().is_err());
+        assert!("🔇= ".parse::<ReactionCommand>().is_err());
+    }
+
+    #[test]
+    fn reaction_command_matches() {
+        let cmd: ReactionCommand = "🛑=shutdown".parse().unwrap();
+        assert!(cmd.matches("🛑"));
+        assert!(cmd.matches("🛑\u{fe0f}"));
+        assert!(!cmd.matches("🔇"));
+    }
+
     #[test]
     fn parse_operation_err() {
-        assert!(Operation::try_from(&["halp"][..]).is_err());
         assert!(Operation::try_from(&[""][..]).is_err());
+        assert!(Operation::try_from(&["power", ""][..]).is_err());
         assert!(Operation::try_from(&[][..]).is_err());
+        assert!("  ".parse::<Operation>().is_err());
     }
 }
@@ -0,0 +1,2141 @@
+use crate::{
+    action::{self, Action, Step},
+    channel,
+    command::{Operation, ReactionCommand},
+    encoding::Encoding,
+    relay::Severity,
+    schema,
+    secret::Secret,
+    template::Template,
+};
+use anyhow::{anyhow, Context, Result};
+use clap::ValueEnum;
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId, UserId};
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, fmt, net::SocketAddr, path::Path, path::PathBuf};
+
+/// Placeholders for the status template, in addition to the status fields of each channel and the
+/// configured fields.
+pub(crate) const STATUS_TEMPLATE_PLACEHOLDERS: &[&str] =
+    &["station", "timestamp", "channels", "fields"];
+
+pub(crate) const MESSAGE_TEMPLATE_PLACEHOLDERS: &[&str] = &["station", "timestamp", "message"];
+
+pub(crate) const RELAY_TEMPLATE_PLACEHOLDERS: &[&str] =
+    &["station", "timestamp", "topic", "payload", "severity"];
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+
+    pub matrix: MatrixConfig,
+
+    /// Optional generic webhook frontend, used alongside Matrix
+    #[serde(default)]
+    pub webhook: Option<WebhookConfig>,
+
+    /// Optional HTTP API for status and commands, used alongside Matrix
+    #[serde(default)]
+    pub api: Option<ApiConfig>,
+
+    pub station: StationConfig,
+
+    /// Switchable outputs of the station
+    #[serde(default = "default_channels")]
+    pub channels: Vec<ChannelConfig>,
+
+    /// Other status fields to show and alert on
+    #[serde(default)]
+    pub fields: Vec<FieldConfig>,
+
+    /// Serial port settings, used when `station.transport` is `serial`
+    #[serde(default)]
+    pub serial: Option<SerialConfig>,
+
+    /// Signing of command and status messages, off unless given
+    #[serde(default)]
+    pub signing: Option<SigningConfig>,
+
+    /// Matrix rooms to send messages to and listen for commands from
+    #[serde(default)]
+    pub rooms: Vec<OwnedRoomId>,
+
+    #[serde(default)]
+    pub permissions: Permissions,
+
+    #[serde(default)]
+    pub templates: Templates,
+
+    #[serde(default)]
+    pub commands: CommandConfig,
+
+    /// Operations that send a fixed payload
+    #[serde(default)]
+    pub operations: Vec<CustomOperationConfig>,
+
+    /// Named sequences of operations
+    #[serde(default)]
+    pub macros: Vec<MacroConfig>,
+
+    #[serde(default)]
+    pub connectivity: ConnectivityConfig,
+
+    /// Other MQTT topics to relay to rooms
+    #[serde(default)]
+    pub relays: Vec<RelayConfig>,
+
+    /// Maximum number of status updates to retain while Matrix is unreachable
+    #[serde(default = "default_status_buffer_size")]
+    pub status_buffer_size: usize,
+
+    /// Address to listen on for observability/metrics endpoints
+    #[serde(default = "default_observability_address")]
+    pub observability_address: SocketAddr,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MqttConfig {
+    /// Brokers to connect to, in order of preference (a single broker may be given as a string)
+    #[serde(
+        rename = "broker",
+        default = "default_mqtt_brokers",
+        deserialize_with = "one_or_many"
+    )]
+    pub brokers: Vec<String>,
+
+    #[serde(default = "default_mqtt_client_id")]
+    pub client_id: String,
+
+    /// MQTT QoS, must be 0, 1 or 2
+    #[serde(default)]
+    pub qos: i32,
+
+    #[serde(default)]
+    pub username: String,
+
+    #[serde(default)]
+    pub password: Option<Secret>,
+
+    /// File to read the password from, as an alternative to `password`
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub tls: Option<MqttTlsConfig>,
+
+    /// MQTT protocol version, 3 (3.1.1) or 5
+    #[serde(default = "default_mqtt_version")]
+    pub version: u32,
+
+    /// Topic on which the bot publishes (retained) whether it is `online` or `offline`, the
+    /// latter via the last will and testament if the bot dies
+    #[serde(default)]
+    pub availability_topic: Option<String>,
+}
+
+impl MqttConfig {
+    /// TLS is used if it is configured or implied by the broker URI.
+    pub(crate) fn uses_tls(&self, broker: &str) -> bool {
+        self.tls.is_some() || is_tls_uri(broker)
+    }
+
+    pub(crate) fn password(&self) -> &str {
+        self.password
+            .as_ref()
+            .map(Secret::expose)
+            .unwrap_or_default()
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            brokers: default_mqtt_brokers(),
+            client_id: default_mqtt_client_id(),
+            qos: 0,
+            username: String::default(),
+            password: None,
+            password_file: None,
+            tls: None,
+            version: default_mqtt_version(),
+            availability_topic: None,
+        }
+    }
+}
+
+fn default_mqtt_version() -> u32 {
+    3
+}
+
+const TLS_URI_SCHEMES: &[&str] = &["ssl://", "mqtts://", "wss://"];
+
+fn is_tls_uri(uri: &str) -> bool {
+    TLS_URI_SCHEMES.iter().any(|s| uri.starts_with(s))
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MqttTlsConfig {
+    /// CA certificates (PEM) to trust when verifying the broker, instead of the system defaults
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+
+    /// Client certificate (PEM) to present to the broker
+    #[serde(default)]
+    pub cert_file: Option<PathBuf>,
+
+    /// Private key (PEM) for the client certificate
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+
+    /// Check that the broker's certificate matches its hostname
+    #[serde(default = "default_true")]
+    pub verify_hostname: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MatrixConfig {
+    pub username: OwnedUserId,
+
+    #[serde(default)]
+    pub password: Option<Secret>,
+
+    /// File to read the password from, as an alternative to `password`
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
+
+    pub storage: PathBuf,
+}
+
+impl MatrixConfig {
+    pub(crate) fn password(&self) -> &str {
+        self.password
+            .as_ref()
+            .map(Secret::expose)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct WebhookConfig {
+    /// Address to listen for command requests on
+    pub listen_address: SocketAddr,
+
+    /// Bearer token that command requests must present
+    #[serde(default)]
+    pub token: Option<Secret>,
+
+    /// File to read the token from, as an alternative to `token`
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+
+    /// URL that replies and status notifications are posted to
+    pub url: String,
+
+    /// Users that command requests may be made as, whose permissions apply to them
+    #[serde(default)]
+    pub senders: Vec<OwnedUserId>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ApiConfig {
+    /// Address to listen for requests on
+    pub listen_address: SocketAddr,
+
+    /// Number of status reports kept for the history endpoint
+    #[serde(default = "default_api_history_size")]
+    pub history_size: usize,
+
+    #[serde(default)]
+    pub clients: Vec<ApiClientConfig>,
+}
+
+fn default_api_history_size() -> usize {
+    100
+}
+
+/// A client of the HTTP API, which is treated as a user for permissions.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ApiClientConfig {
+    /// Identity whose permissions apply, e.g. `@website:api`
+    pub user: OwnedUserId,
+
+    /// Bearer token that requests must present
+    #[serde(default)]
+    pub token: Option<Secret>,
+
+    /// File to read the token from, as an alternative to `token`
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+}
+
+impl ApiClientConfig {
+    pub(crate) fn token(&self) -> &str {
+        self.token.as_ref().map(Secret::expose).unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct StationConfig {
+    pub name: String,
+
+    /// How the bot communicates with the station
+    #[serde(default)]
+    pub transport: TransportKind,
+
+    /// Topic to listen for status messages on (MQTT only)
+    #[serde(default)]
+    pub status_topic: String,
+
+    /// Topic to send command messages on (MQTT only)
+    #[serde(default)]
+    pub command_topic: String,
+
+    /// Topic the station replies to commands on (MQTT v5 only)
+    #[serde(default)]
+    pub response_topic: String,
+
+    /// Version of the message format to send commands in
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+
+    /// How messages are serialised, those received are decoded as whatever they appear to be
+    #[serde(default)]
+    pub encoding: Encoding,
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+/// A switchable output of the station (e.g. TX power, PTT, a linear amplifier).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ChannelConfig {
+    /// Name used in commands (e.g. `power` for `power on`)
+    pub name: String,
+
+    /// Name shown in status messages
+    pub label: String,
+
+    /// Status field reporting whether the channel is enabled
+    pub status_field: String,
+
+    /// Status field reporting whether the channel is active (e.g. keyed), if there is one
+    #[serde(default)]
+    pub active_field: Option<String>,
+
+    /// Command field that enables or disables the channel
+    pub command_field: String,
+
+    /// Word that enables the channel in commands
+    #[serde(default = "default_enable_word")]
+    pub enable_word: String,
+
+    /// Word that disables the channel in commands
+    #[serde(default = "default_disable_word")]
+    pub disable_word: String,
+
+    /// Shown for the enabled field, when true and false
+    #[serde(default = "default_enabled_labels")]
+    pub enabled_labels: [String; 2],
+
+    /// Shown for the active field, when true and false
+    #[serde(default = "default_active_labels")]
+    pub active_labels: [String; 2],
+
+    /// Disabled by the `shutdown` command
+    #[serde(default = "default_true")]
+    pub shutdown: bool,
+}
+
+fn default_enable_word() -> String {
+    "on".to_string()
+}
+
+fn default_disable_word() -> String {
+    "off".to_string()
+}
+
+fn default_enabled_labels() -> [String; 2] {
+    ["ENABLED".to_string(), "DISABLED".to_string()]
+}
+
+fn default_active_labels() -> [String; 2] {
+    ["ON".to_string(), "OFF".to_string()]
+}
+
+/// The outputs of the original remote-closedown hardware.
+pub(crate) fn default_channels() -> Vec<ChannelConfig> {
+    vec![
+        ChannelConfig {
+            name: "power".to_string(),
+            label: "TX Power".to_string(),
+            status_field: "tx_power_enabled".to_string(),
+            active_field: Some("tx_power_active".to_string()),
+            command_field: "enable_tx_power".to_string(),
+            enable_word: default_enable_word(),
+            disable_word: default_disable_word(),
+            enabled_labels: default_enabled_labels(),
+            active_labels: default_active_labels(),
+            shutdown: true,
+        },
+        ChannelConfig {
+            name: "ptt".to_string(),
+            label: "PTT".to_string(),
+            status_field: "ptt_enabled".to_string(),
+            active_field: Some("ptt_active".to_string()),
+            command_field: "enable_ptt".to_string(),
+            enable_word: "enable".to_string(),
+            disable_word: "disable".to_string(),
+            enabled_labels: default_enabled_labels(),
+            active_labels: ["ON AIR".to_string(), "IDLE".to_string()],
+            shutdown: true,
+        },
+    ]
+}
+
+/// How to show a status field that is not part of a channel (e.g. temperature), and the range
+/// outside of which to raise an alert.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FieldConfig {
+    /// Name of the status field, also used as a status template placeholder
+    pub name: String,
+
+    /// Name shown in status messages and alerts, the field name if not given
+    #[serde(default)]
+    pub label: Option<String>,
+
+    /// Shown after the value (e.g. `°C`)
+    #[serde(default)]
+    pub unit: String,
+
+    /// Number of decimal places to show numeric values with
+    #[serde(default)]
+    pub precision: Option<usize>,
+
+    /// Alert when the value is below this
+    #[serde(default)]
+    pub min: Option<f64>,
+
+    /// Alert when the value is above this
+    #[serde(default)]
+    pub max: Option<f64>,
+}
+
+impl FieldConfig {
+    pub(crate) fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TransportKind {
+    /// Commands and status are exchanged via an MQTT broker
+    #[default]
+    Mqtt,
+    /// Commands and status are exchanged as lines of JSON over a serial port
+    Serial,
+}
+
+impl fmt::Display for TransportKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mqtt => write!(f, "mqtt"),
+            Self::Serial => write!(f, "serial"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SerialConfig {
+    /// Serial port device (e.g. `/dev/ttyUSB0`)
+    pub port: String,
+
+    #[serde(default = "default_serial_baud_rate")]
+    pub baud_rate: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SigningConfig {
+    /// Key shared with the station
+    #[serde(default)]
+    pub key: Option<Secret>,
+
+    /// File to read the key from, as an alternative to `key`
+    #[serde(default)]
+    pub key_file: Option<PathBuf>,
+
+    /// Maximum age (in seconds) of a signed message for it to be accepted, also the window in
+    /// which replays are detected
+    #[serde(default = "default_signing_max_age")]
+    pub max_age: u64,
+}
+
+fn default_signing_max_age() -> u64 {
+    30
+}
+
+/// Which operations Matrix users are allowed to request.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Permissions {
+    /// Operations allowed for users that are not listed in `users`, all operations if not given
+    #[serde(default)]
+    pub default: Option<Vec<Operation>>,
+
+    #[serde(default)]
+    pub users: HashMap<OwnedUserId, Vec<Operation>>,
+}
+
+impl Permissions {
+    pub(crate) fn allows(&self, user: &UserId, op: &Operation) -> bool {
+        match self.users.get(user).or(self.default.as_ref()) {
+            Some(ops) => ops.contains(op),
+            None => true,
+        }
+    }
+
+    /// All operations listed, for checking that they exist.
+    fn operations(&self) -> impl Iterator<Item = &Operation> {
+        self.default.iter().chain(self.users.values()).flatten()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Templates {
+    /// Posted when the station status changes
+    #[serde(default = "default_status_template")]
+    pub status: Template,
+
+    /// Posted when the station sends a message
+    #[serde(default = "default_message_template")]
+    pub message: Template,
+}
+
+impl Default for Templates {
+    fn default() -> Self {
+        Self {
+            status: default_status_template(),
+            message: default_message_template(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CommandConfig {
+    /// Maximum age (in seconds) of a command message for it to be acted upon
+    #[serde(default = "default_command_max_age")]
+    pub max_age: u64,
+
+    /// Operations to perform when a status message is reacted to with an emoji
+    #[serde(default)]
+    pub reactions: Vec<ReactionCommand>,
+
+    /// Time (in seconds) to wait for the station to reply to a command (MQTT v5), or otherwise for
+    /// its status to show that the command took effect
+    #[serde(default = "default_command_reply_timeout")]
+    pub reply_timeout: u64,
+}
+
+impl Default for CommandConfig {
+    fn default() -> Self {
+        Self {
+            max_age: default_command_max_age(),
+            reactions: Vec::default(),
+            reply_timeout: default_command_reply_timeout(),
+        }
+    }
+}
+
+fn default_command_reply_timeout() -> u64 {
+    30
+}
+
+/// An operation that sends a fixed payload, e.g. to start a beacon.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CustomOperationConfig {
+    /// Used as the command (e.g. `beacon start`)
+    pub name: String,
+
+    /// Shown in help
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Topic to publish to, the station's command topic if not given (MQTT only)
+    #[serde(default)]
+    pub topic: Option<String>,
+
+    /// Sent as JSON
+    pub payload: serde_json::Value,
+}
+
+/// A sequence of operations with delays in between, e.g. `["ptt disable", "wait 5s", "power off"]`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct MacroConfig {
+    /// Used as the command (e.g. `maintenance`)
+    pub name: String,
+
+    /// Shown in help
+    #[serde(default)]
+    pub description: Option<String>,
+
+    pub steps: Vec<Step>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RelayConfig {
+    /// Topic filter to relay messages from (may contain wildcards)
+    pub topic: String,
+
+    /// Rooms to post to, all rooms (and other frontends) if empty
+    #[serde(default)]
+    pub rooms: Vec<OwnedRoomId>,
+
+    #[serde(default = "default_relay_template")]
+    pub template: Template,
+
+    /// Additional placeholders, taken from JSON payloads by JSON pointer (e.g. `"/alarm/text"`)
+    #[serde(default)]
+    pub fields: HashMap<String, String>,
+
+    /// JSON pointer to the severity of a message
+    #[serde(default)]
+    pub severity_field: Option<String>,
+
+    /// Messages less severe than this are not relayed
+    #[serde(default)]
+    pub min_severity: Option<Severity>,
+
+    /// Minimum time (in seconds) between relayed messages, others are counted and dropped
+    #[serde(default)]
+    pub min_interval: u64,
+}
+
+fn default_relay_template() -> Template {
+    Template::new("**{station}** {topic}: {payload}")
+}
+
+/// Announcements of the bot losing and regaining its connections to MQTT and Matrix.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ConnectivityConfig {
+    #[serde(default = "default_true")]
+    pub announce: bool,
+
+    /// Minimum time (in seconds) between announcements of lost connections to the same service,
+    /// so that a flapping connection does not flood the rooms
+    #[serde(default = "default_connectivity_min_interval")]
+    pub min_interval: u64,
+}
+
+impl Default for ConnectivityConfig {
+    fn default() -> Self {
+        Self {
+            announce: true,
+            min_interval: default_connectivity_min_interval(),
+        }
+    }
+}
+
+fn default_connectivity_min_interval() -> u64 {
+    300
+}
+
+fn default_mqtt_brokers() -> Vec<String> {
+    vec!["tcp://localhost:1883".to_string()]
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(one) => vec![one],
+        OneOrMany::Many(many) => many,
+    })
+}
+
+fn default_mqtt_client_id() -> String {
+    "matrix-remote-closedown".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_serial_baud_rate() -> u32 {
+    9600
+}
+
+fn default_status_buffer_size() -> usize {
+    100
+}
+
+fn default_observability_address() -> SocketAddr {
+    "127.0.0.1:9090".parse().unwrap()
+}
+
+fn default_command_max_age() -> u64 {
+    60
+}
+
+fn default_status_template() -> Template {
+    Template::new("**{station}** at {timestamp}<br>\n{channels}")
+}
+
+fn default_message_template() -> Template {
+    Template::new("**{station}** at {timestamp}<br>\nMessage: {message}")
+}
+
+impl Config {
+    /// Loads configuration from an optional file, with `overrides` (from the command line)
+    /// taking precedence over values in the file.
+    ///
+    /// Only syntax is validated, see `check` for further validation.
+    pub(crate) fn load_unchecked(
+        path: Option<&Path>,
+        overrides: Vec<(&str, toml::Value)>,
+    ) -> Result<Self> {
+        let mut config = Self::from_table(load_table(path, overrides)?)?;
+
+        resolve_secret(
+            "matrix.password",
+            &mut config.matrix.password,
+            &config.matrix.password_file,
+        )?;
+        if config.matrix.password.is_none() {
+            return Err(anyhow!(
+                "One of matrix.password or matrix.password_file must be given"
+            ));
+        }
+        if let Some(webhook) = &mut config.webhook {
+            resolve_secret("webhook.token", &mut webhook.token, &webhook.token_file)?;
+            if webhook.token.is_none() {
+                return Err(anyhow!(
+                    "One of webhook.token or webhook.token_file must be given"
+                ));
+            }
+        }
+        if let Some(api) = &mut config.api {
+            for (i, client) in api.clients.iter_mut().enumerate() {
+                resolve_secret("api.clients.token", &mut client.token, &client.token_file)?;
+                if client.token.is_none() {
+                    return Err(anyhow!(
+                        "One of token or token_file must be given for api.clients[{}]",
+                        i
+                    ));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Loads configuration (as per `load_unchecked`), failing if it has any errors.
+    pub(crate) fn load(path: Option<&Path>, overrides: Vec<(&str, toml::Value)>) -> Result<Self> {
+        Self::load_unchecked(path, overrides)?.checked()
+    }
+
+    /// Loads configuration for the console (as per `load`), which is used when Matrix is not, so
+    /// needs none of the settings for Matrix or the other frontends.
+    pub(crate) fn load_console(
+        path: Option<&Path>,
+        overrides: Vec<(&str, toml::Value)>,
+    ) -> Result<Self> {
+        let mut table = load_table(path, overrides)?;
+        table.remove("webhook");
+        table.remove("api");
+        // Stand-ins, nothing is sent to or received from Matrix
+        let mut matrix = toml::Table::new();
+        matrix.insert("username".to_string(), "@console:localhost".into());
+        matrix.insert("storage".to_string(), "".into());
+        table.insert("matrix".to_string(), matrix.into());
+
+        let mut config = Self::from_table(table)?;
+        // Availability is that of the bot, not of console sessions
+        config.mqtt.availability_topic = None;
+        config.checked()
+    }
+
+    /// Parses configuration, resolving the secrets needed to communicate with the station.
+    fn from_table(table: toml::Table) -> Result<Self> {
+        let mut config: Self = toml::Value::Table(table)
+            .try_into()
+            .context("Invalid configuration")?;
+
+        resolve_secret(
+            "mqtt.password",
+            &mut config.mqtt.password,
+            &config.mqtt.password_file,
+        )?;
+        resolve_signing_key(&mut config.signing)?;
+
+        Ok(config)
+    }
+
+    /// Fails if the configuration has any errors, logging any warnings.
+    fn checked(self) -> Result<Self> {
+        let errors: Vec<String> = self
+            .check()
+            .into_iter()
+            .filter_map(|issue| match issue {
+                Issue::Error(e) => Some(e),
+                Issue::Warning(w) => {
+                    log::warn!("Configuration: {}", w);
+                    None
+                }
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Ok(self)
+        } else {
+            Err(anyhow!("Invalid configuration: {}", errors.join(", ")))
+        }
+    }
+
+    /// Checks the configuration for problems that cannot be caught when parsing it.
+    pub(crate) fn check(&self) -> Vec<Issue> {
+        let mut issues = check_station_link(&self.link());
+
+        if self.status_buffer_size == 0 {
+            issues.push(Issue::Error(
+                "status_buffer_size must be greater than 0".to_string(),
+            ));
+        }
+
+        if self.rooms.is_empty() {
+            issues.push(Issue::Warning(
+                "no rooms are configured, status will not be posted anywhere".to_string(),
+            ));
+        }
+        for (i, room) in self.rooms.iter().enumerate() {
+            if self.rooms[..i].contains(room) {
+                issues.push(Issue::Warning(format!("room {} is listed twice", room)));
+            }
+        }
+
+        if self.permissions.users.contains_key(&self.matrix.username) {
+            issues.push(Issue::Warning(format!(
+                "permissions.users lists the bot user {}, commands from it are always ignored",
+                self.matrix.username
+            )));
+        }
+
+        for (i, reaction) in self.commands.reactions.iter().enumerate() {
+            if self.commands.reactions[..i]
+                .iter()
+                .any(|r| r.matches(&reaction.key))
+            {
+                issues.push(Issue::Error(format!(
+                    "commands.reactions has more than one command for {}",
+                    reaction.key
+                )));
+            }
+
+            let permitted = self
+                .permissions
+                .default
+                .as_ref()
+                .is_none_or(|ops| ops.contains(&reaction.op))
+                || self
+                    .permissions
+                    .users
+                    .values()
+                    .any(|ops| ops.contains(&reaction.op));
+            if !permitted {
+                issues.push(Issue::Warning(format!(
+                    "commands.reactions has {} for `{}`, which no user is permitted to use",
+                    reaction.key, reaction.op
+                )));
+            }
+        }
+
+        issues.append(&mut self.check_operations());
+
+        for op in self.permissions.operations() {
+            if let Err(e) = action::resolve(self, op) {
+                issues.push(Issue::Error(format!("permissions: {}", e)));
+            }
+        }
+        for reaction in &self.commands.reactions {
+            if let Err(e) = action::resolve(self, &reaction.op) {
+                issues.push(Issue::Error(format!("commands.reactions: {}", e)));
+            }
+        }
+
+        issues.append(&mut self.check_fields());
+
+        let mut placeholders = channel::status_placeholders(&self.channels);
+        placeholders.extend(self.fields.iter().map(|f| f.name.as_str()));
+        if let Err(e) = self.templates.status.validate(&placeholders) {
+            issues.push(Issue::Error(format!("templates.status: {}", e)));
+        }
+        if let Err(e) = self
+            .templates
+            .message
+            .validate(MESSAGE_TEMPLATE_PLACEHOLDERS)
+        {
+            issues.push(Issue::Error(format!("templates.message: {}", e)));
+        }
+
+        for (i, relay) in self.relays.iter().enumerate() {
+            issues.append(&mut check_relay(i, relay, &self.station.transport));
+        }
+
+        if let Some(webhook) = &self.webhook {
+            if webhook.senders.is_empty() {
+                issues.push(Issue::Warning(
+                    "webhook has no senders, so no command request will be accepted".to_string(),
+                ));
+            }
+        }
+        if let Some(api) = &self.api {
+            issues.append(&mut check_api(api));
+        }
+
+        issues
+    }
+
+    /// The parts of the configuration needed to communicate with the station.
+    pub(crate) fn link(&self) -> LinkConfig {
+        LinkConfig {
+            mqtt: self.mqtt.clone(),
+            station: self.station.clone(),
+            serial: self.serial.clone(),
+            signing: self.signing.clone(),
+            channels: self.channels.clone(),
+            relay_topics: self.relays.iter().map(|r| r.topic.clone()).collect(),
+        }
+    }
+
+    fn check_fields(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let channel_fields = channel::status_placeholders(&self.channels);
+        for (i, f) in self.fields.iter().enumerate() {
+            if f.name.is_empty() {
+                issues.push(Issue::Error(format!("fields[{}] must have a name", i)));
+            }
+            if channel_fields.contains(&f.name.as_str()) {
+                issues.push(Issue::Error(format!(
+                    "field {} is already used by a channel or the status template",
+                    f.name
+                )));
+            }
+            if self.fields[..i].iter().any(|o| o.name == f.name) {
+                issues.push(Issue::Error(format!(
+                    "field {} is defined more than once",
+                    f.name
+                )));
+            }
+            if let (Some(min), Some(max)) = (f.min, f.max) {
+                if min >= max {
+                    issues.push(Issue::Error(format!(
+                        "field {} min must be less than max",
+                        f.name
+                    )));
+                }
+            }
+        }
+
+        issues
+    }
+
+    fn check_operations(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+
+        let channel_commands = channel::commands(&self.channels);
+        let names = self
+            .operations
+            .iter()
+            .map(|o| &o.name)
+            .chain(self.macros.iter().map(|m| &m.name))
+            .collect::<Vec<_>>();
+        for (i, name) in names.iter().enumerate() {
+            if name.is_empty()
+                || **name != name.to_lowercase()
+                || name.split_whitespace().collect::<Vec<_>>().join(" ") != **name
+            {
+                issues.push(Issue::Error(format!(
+                    "operation \"{}\" must be named with lowercase words separated by single spaces",
+                    name
+                )));
+            }
+            if ["help", "shutdown"].contains(&name.as_str()) || channel_commands.contains(name) {
+                issues.push(Issue::Error(format!(
+                    "operation \"{}\" clashes with a built in or channel command",
+                    name
+                )));
+            }
+            if names[..i].contains(name) {
+                issues.push(Issue::Error(format!(
+                    "operation \"{}\" is defined more than once",
+                    name
+                )));
+            }
+        }
+
+        for o in &self.operations {
+            if let Some(topic) = &o.topic {
+                if self.station.transport != TransportKind::Mqtt {
+                    issues.push(Issue::Error(format!(
+                        "operation \"{}\" has a topic, which can only be used with the MQTT transport",
+                        o.name
+                    )));
+                }
+                if let Err(e) = check_topic(topic, false) {
+                    issues.push(Issue::Error(format!(
+                        "operation \"{}\" topic {}",
+                        o.name, e
+                    )));
+                }
+            }
+        }
+
+        for m in &self.macros {
+            if m.steps.is_empty() {
+                issues.push(Issue::Warning(format!("macro \"{}\" has no steps", m.name)));
+            }
+            for step in &m.steps {
+                if let Step::Run(op) = step {
+                    match action::resolve(self, op) {
+                        Ok(Action::Macro(_)) => issues.push(Issue::Error(format!(
+                            "macro \"{}\" cannot run another macro ({})",
+                            m.name, op
+                        ))),
+                        Ok(_) => {}
+                        Err(e) => issues.push(Issue::Error(format!("macro \"{}\": {}", m.name, e))),
+                    }
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Applies the parts of a newly loaded configuration that can be changed without
+    /// reconnecting to MQTT or Matrix.
+    pub(crate) fn reload(&mut self, new: Config) {
+        if new.mqtt != self.mqtt {
+            log::warn!("MQTT configuration changed, restart to apply");
+        }
+        if new.matrix != self.matrix {
+            log::warn!("Matrix configuration changed, restart to apply");
+        }
+        if new.webhook != self.webhook {
+            log::warn!("Webhook configuration changed, restart to apply");
+        }
+        if new.api != self.api {
+            log::warn!("API configuration changed, restart to apply");
+        }
+        if new.station != self.station || new.serial != self.serial || new.signing != self.signing {
+            log::warn!("Station configuration changed, restart to apply");
+        }
+        if new.status_buffer_size != self.status_buffer_size {
+            log::warn!("Status buffer size changed, restart to apply");
+        }
+        if new.observability_address != self.observability_address {
+            log::warn!("Observability address changed, restart to apply");
+        }
+
+        self.channels = new.channels;
+        self.fields = new.fields;
+        self.rooms = new.rooms;
+        self.permissions = new.permissions;
+        self.templates = new.templates;
+        self.commands = new.commands;
+        self.operations = new.operations;
+        self.macros = new.macros;
+        self.connectivity = new.connectivity;
+
+        if new
+            .relays
+            .iter()
+            .map(|r| &r.topic)
+            .ne(self.relays.iter().map(|r| &r.topic))
+        {
+            log::warn!("Relay topics changed, restart to apply");
+        }
+        self.relays = new.relays;
+    }
+}
+
+/// The subset of the configuration needed to communicate with the station, for subcommands that
+/// do not use Matrix.
+#[derive(Clone, Debug, Deserialize)]
+pub(crate) struct LinkConfig {
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+
+    pub station: StationConfig,
+
+    #[serde(default)]
+    pub serial: Option<SerialConfig>,
+
+    #[serde(default)]
+    pub signing: Option<SigningConfig>,
+
+    #[serde(default = "default_channels")]
+    pub channels: Vec<ChannelConfig>,
+
+    /// Topics relayed to rooms, only set when running the bot itself
+    #[serde(skip)]
+    pub relay_topics: Vec<String>,
+}
+
+impl LinkConfig {
+    pub(crate) fn load(path: Option<&Path>, overrides: Vec<(&str, toml::Value)>) -> Result<Self> {
+        let mut config: Self = toml::Value::Table(load_table(path, overrides)?)
+            .try_into()
+            .context("Invalid configuration")?;
+
+        resolve_secret(
+            "mqtt.password",
+            &mut config.mqtt.password,
+            &config.mqtt.password_file,
+        )?;
+        resolve_signing_key(&mut config.signing)?;
+
+        let errors: Vec<String> = check_station_link(&config)
+            .into_iter()
+            .map(|issue| issue.to_string())
+            .collect();
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(anyhow!("Invalid configuration: {}", errors.join(", ")))
+        }
+    }
+}
+
+/// Reads the configuration file (if any) and applies `overrides` to it.
+fn load_table(path: Option<&Path>, overrides: Vec<(&str, toml::Value)>) -> Result<toml::Table> {
+    let mut table = match path {
+        Some(path) => std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?
+            .parse::<toml::Table>()
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?,
+        None => toml::Table::new(),
+    };
+
+    for (key, value) in overrides {
+        // A secret given on the command line replaces one from the file, in either form
+        if let Some(secret) = SECRETS
+            .iter()
+            .find(|s| key == **s || key == format!("{}_file", s))
+        {
+            remove_value(&mut table, secret);
+            remove_value(&mut table, &format!("{}_file", secret));
+        }
+        set_value(&mut table, key, value)?;
+    }
+
+    Ok(table)
+}
+
+fn check_station_link(link: &LinkConfig) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if !schema::SUPPORTED_VERSIONS.contains(&link.station.schema_version) {
+        issues.push(Issue::Error(format!(
+            "station.schema_version must be one of {:?} (got {})",
+            schema::SUPPORTED_VERSIONS,
+            link.station.schema_version
+        )));
+    }
+
+    match link.station.transport {
+        TransportKind::Mqtt => {
+            if !(0..=2).contains(&link.mqtt.qos) {
+                issues.push(Issue::Error(format!(
+                    "mqtt.qos must be 0, 1 or 2 (got {})",
+                    link.mqtt.qos
+                )));
+            }
+
+            if link.mqtt.brokers.is_empty() {
+                issues.push(Issue::Error(
+                    "mqtt.broker must list at least one broker".to_string(),
+                ));
+            }
+            for (i, broker) in link.mqtt.brokers.iter().enumerate() {
+                if link.mqtt.brokers[..i].contains(broker) {
+                    issues.push(Issue::Warning(format!(
+                        "mqtt.broker lists {} twice",
+                        broker
+                    )));
+                }
+            }
+
+            if let Some(topic) = &link.mqtt.availability_topic {
+                if let Err(e) = check_topic(topic, false) {
+                    issues.push(Issue::Error(format!("mqtt.availability_topic {}", e)));
+                }
+            }
+
+            if let Some(tls) = &link.mqtt.tls {
+                issues.append(&mut check_mqtt_tls(&link.mqtt.brokers, tls));
+            }
+
+            if let Err(e) = check_topic(&link.station.status_topic, true) {
+                issues.push(Issue::Error(format!("station.status_topic {}", e)));
+            }
+            if let Err(e) = check_topic(&link.station.command_topic, false) {
+                issues.push(Issue::Error(format!("station.command_topic {}", e)));
+            }
+
+            match link.mqtt.version {
+                3 => {
+                    if !link.station.response_topic.is_empty() {
+                        issues.push(Issue::Warning(
+                            "station.response_topic is only used with MQTT v5 (mqtt.version = 5)"
+                                .to_string(),
+                        ));
+                    }
+                }
+                5 => {
+                    if let Err(e) = check_topic(&link.station.response_topic, false) {
+                        issues.push(Issue::Error(format!("station.response_topic {}", e)));
+                    }
+                }
+                v => issues.push(Issue::Error(format!(
+                    "mqtt.version must be 3 or 5 (got {})",
+                    v
+                ))),
+            }
+        }
+        TransportKind::Serial => {
+            if link.serial.is_none() {
+                issues.push(Issue::Error(
+                    "station.transport is serial, but there is no [serial] section".to_string(),
+                ));
+            }
+            if link.station.encoding != Encoding::Json {
+                issues.push(Issue::Error(format!(
+                    "station.encoding {} cannot be used with the serial transport, which is line based",
+                    link.station.encoding
+                )));
+            }
+        }
+    }
+
+    issues.append(&mut check_channels(&link.channels));
+
+    if let Some(signing) = &link.signing {
+        if signing.key.as_ref().is_some_and(|k| k.expose().len() < 16) {
+            issues.push(Issue::Warning(
+                "signing.key is short, use at least 16 random characters".to_string(),
+            ));
+        }
+        if signing.max_age == 0 {
+            issues.push(Issue::Error(
+                "signing.max_age must be greater than 0".to_string(),
+            ));
+        }
+        if link.station.encoding != Encoding::Json {
+            issues.push(Issue::Error(format!(
+                "station.encoding {} cannot be used with signing, whose envelopes are JSON",
+                link.station.encoding
+            )));
+        }
+    }
+
+    issues
+}
+
+fn check_channels(channels: &[ChannelConfig]) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if channels.is_empty() {
+        issues.push(Issue::Warning(
+            "no channels are configured, only help can be used".to_string(),
+        ));
+    }
+
+    for (i, c) in channels.iter().enumerate() {
+        let name = if c.name.is_empty() {
+            format!("channels[{}]", i)
+        } else {
+            format!("channel {}", c.name)
+        };
+
+        if c.name.is_empty() || c.name.split_whitespace().collect::<Vec<_>>().join(" ") != c.name {
+            issues.push(Issue::Error(format!(
+                "{} must have a name of one or more words separated by single spaces",
+                name
+            )));
+        }
+        if c.name != c.name.to_lowercase() {
+            issues.push(Issue::Error(format!("{} name must be lowercase", name)));
+        }
+        if ["help", "shutdown"].contains(&c.name.as_str()) {
+            issues.push(Issue::Error(format!(
+                "{} clashes with a built in command",
+                name
+            )));
+        }
+        if channels[..i].iter().any(|o| o.name == c.name) {
+            issues.push(Issue::Error(format!("{} is defined more than once", name)));
+        }
+
+        for word in [&c.enable_word, &c.disable_word] {
+            if word.is_empty() || word.contains(' ') || *word != word.to_lowercase() {
+                issues.push(Issue::Error(format!(
+                    "{} enable_word and disable_word must be single lowercase words",
+                    name
+                )));
+            }
+        }
+        if c.enable_word == c.disable_word {
+            issues.push(Issue::Error(format!(
+                "{} enable_word and disable_word must differ",
+                name
+            )));
+        }
+
+        if c.status_field.is_empty() || c.command_field.is_empty() {
+            issues.push(Issue::Error(format!(
+                "{} status_field and command_field must not be empty",
+                name
+            )));
+        }
+        let fields = channels[..i]
+            .iter()
+            .flat_map(|o| [Some(&o.status_field), o.active_field.as_ref()])
+            .flatten()
+            .collect::<Vec<_>>();
+        for field in [Some(&c.status_field), c.active_field.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if fields.contains(&field) || STATUS_TEMPLATE_PLACEHOLDERS.contains(&field.as_str()) {
+                issues.push(Issue::Error(format!(
+                    "{} status field {} is already used",
+                    name, field
+                )));
+            }
+        }
+        if channels[..i]
+            .iter()
+            .any(|o| o.command_field == c.command_field)
+        {
+            issues.push(Issue::Error(format!(
+                "{} command field {} is already used",
+                name, c.command_field
+            )));
+        }
+    }
+
+    issues
+}
+
+fn check_api(api: &ApiConfig) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if api.clients.is_empty() {
+        issues.push(Issue::Warning(
+            "api has no clients, so no request will be accepted".to_string(),
+        ));
+    }
+    if api.history_size == 0 {
+        issues.push(Issue::Error(
+            "api.history_size must be greater than 0".to_string(),
+        ));
+    }
+
+    for (i, client) in api.clients.iter().enumerate() {
+        if client.token().len() < 16 {
+            issues.push(Issue::Warning(format!(
+                "api.clients[{}] ({}) has a short token, use at least 16 random characters",
+                i, client.user
+            )));
+        }
+        if api.clients[..i].iter().any(|c| c.token() == client.token()) {
+            issues.push(Issue::Error(format!(
+                "api.clients[{}] ({}) has the same token as another client",
+                i, client.user
+            )));
+        }
+    }
+
+    issues
+}
+
+fn check_relay(index: usize, relay: &RelayConfig, transport: &TransportKind) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    if *transport != TransportKind::Mqtt {
+        issues.push(Issue::Warning(format!(
+            "relays[{}] will not receive anything, relays are only supported with MQTT",
+            index
+        )));
+    }
+
+    if let Err(e) = check_topic(&relay.topic, true) {
+        issues.push(Issue::Error(format!("relays[{}].topic {}", index, e)));
+    }
+
+    let pointers = relay
+        .fields
+        .iter()
+        .map(|(name, pointer)| (format!("fields.{}", name), pointer))
+        .chain(
+            relay
+                .severity_field
+                .iter()
+                .map(|pointer| ("severity_field".to_string(), pointer)),
+        );
+    for (name, pointer) in pointers {
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            issues.push(Issue::Error(format!(
+                "relays[{}].{} must be a JSON pointer starting with \"/\" (got \"{}\")",
+                index, name, pointer
+            )));
+        }
+    }
+
+    let mut placeholders = RELAY_TEMPLATE_PLACEHOLDERS.to_vec();
+    placeholders.extend(relay.fields.keys().map(String::as_str));
+    if let Err(e) = relay.template.validate(&placeholders) {
+        issues.push(Issue::Error(format!("relays[{}].template: {}", index, e)));
+    }
+
+    if relay.min_severity.is_some() && relay.severity_field.is_none() {
+        issues.push(Issue::Warning(format!(
+            "relays[{}].min_severity is set without severity_field, all messages are treated as info",
+            index
+        )));
+    }
+
+    issues
+}
+
+fn check_mqtt_tls(brokers: &[String], tls: &MqttTlsConfig) -> Vec<Issue> {
+    let mut issues = Vec::new();
+
+    for broker in brokers.iter().filter(|b| !is_tls_uri(b)) {
+        issues.push(Issue::Error(format!(
+            "mqtt.tls is set, but the broker URI {} does not use TLS (use ssl://)",
+            broker
+        )));
+    }
+
+    for (name, path) in [
+        ("ca_file", &tls.ca_file),
+        ("cert_file", &tls.cert_file),
+        ("key_file", &tls.key_file),
+    ] {
+        if let Some(path) = path {
+            match std::fs::File::open(path) {
+                Ok(_) if !path.is_file() => issues.push(Issue::Error(format!(
+                    "mqtt.tls.{} {} is not a file",
+                    name,
+                    path.display()
+                ))),
+                Ok(_) => {}
+                Err(e) => issues.push(Issue::Error(format!(
+                    "mqtt.tls.{} {} cannot be read ({})",
+                    name,
+                    path.display(),
+                    e
+                ))),
+            }
+        }
+    }
+
+    if tls.cert_file.is_some() != tls.key_file.is_some() {
+        issues.push(Issue::Error(
+            "mqtt.tls.cert_file and mqtt.tls.key_file must be given together".to_string(),
+        ));
+    }
+
+    if !tls.verify_hostname {
+        issues.push(Issue::Warning(
+            "mqtt.tls.verify_hostname is disabled, the broker could be impersonated".to_string(),
+        ));
+    }
+
+    issues
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Issue {
+    Error(String),
+    Warning(String),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error(e) => write!(f, "error: {}", e),
+            Self::Warning(w) => write!(f, "warning: {}", w),
+        }
+    }
+}
+
+/// Checks the syntax of an MQTT topic name (`subscribe == false`) or topic filter
+/// (`subscribe == true`).
+fn check_topic(topic: &str, subscribe: bool) -> std::result::Result<(), &'static str> {
+    if topic.is_empty() {
+        return Err("must not be empty");
+    }
+    if topic.contains('\0') {
+        return Err("must not contain null characters");
+    }
+
+    let levels: Vec<&str> = topic.split('/').collect();
+    for (i, level) in levels.iter().enumerate() {
+        if !subscribe && (level.contains('+') || level.contains('#')) {
+            return Err("must not contain wildcards");
+        }
+        if level.contains('+') && *level != "+" {
+            return Err("must only use \"+\" as an entire level");
+        }
+        if level.contains('#') && (*level != "#" || i != levels.len() - 1) {
+            return Err("must only use \"#\" as the entire last level");
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings that may alternatively be read from a file, given by the same key suffixed with
+/// `_file`.
+const SECRETS: &[&str] = &[
+    "mqtt.password",
+    "matrix.password",
+    "webhook.token",
+    "signing.key",
+];
+
+fn resolve_signing_key(signing: &mut Option<SigningConfig>) -> Result<()> {
+    if let Some(signing) = signing {
+        resolve_secret("signing.key", &mut signing.key, &signing.key_file)?;
+        if signing.key.is_none() {
+            return Err(anyhow!(
+                "One of signing.key or signing.key_file must be given"
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn resolve_secret(name: &str, value: &mut Option<Secret>, file: &Option<PathBuf>) -> Result<()> {
+    match (&value, file) {
+        (Some(_), Some(_)) => Err(anyhow!(
+            "Only one of {} and {}_file may be given",
+            name,
+            name
+        )),
+        (None, Some(path)) => {
+            *value = Some(Secret::read(path)?);
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn remove_value(table: &mut toml::Table, key: &str) {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            if let Some(toml::Value::Table(child)) = table.get_mut(head) {
+                remove_value(child, rest);
+            }
+        }
+        None => {
+            table.remove(key);
+        }
+    }
+}
+
+/// Sets a value in a TOML table given a dotted key (e.g. `mqtt.broker`), creating intermediate
+/// tables as needed.
+fn set_value(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<()> {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let child = table
+                .entry(head)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            match child {
+                toml::Value::Table(child) => set_value(child, rest, value),
+                _ => Err(anyhow!("Config key \"{}\" is not a table", head)),
+            }
+        }
+        None => {
+            table.insert(key.to_string(), value);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINIMAL: &str = r#"
+        [matrix]
+        username = "@bot:example.com"
+        password = "hunter2"
+        storage = "/var/lib/bot"
+
+        [station]
+        name = "mb7pmf"
+        status_topic = "mb7pmf"
+        command_topic = "mb7pmf/command"
+    "#;
+
+    fn load(toml: &str, overrides: Vec<(&str, toml::Value)>) -> Result<Config> {
+        let path = std::env::temp_dir().join(format!(
+            "matrix-remote-closedown-config-test-{}.toml",
+            std::thread::current()
+                .name()
+                .unwrap_or("x")
+                .replace("::", "-")
+        ));
+        std::fs::write(&path, toml)?;
+        Config::load(Some(&path), overrides)
+    }
+
+    #[test]
+    fn load_defaults() {
+        let config = load(MINIMAL, vec![]).unwrap();
+        assert_eq!(config.mqtt, MqttConfig::default());
+        assert_eq!(config.station.name, "mb7pmf");
+        assert!(config.rooms.is_empty());
+        assert_eq!(config.commands.max_age, 60);
+        assert_eq!(config.permissions, Permissions::default());
+    }
+
+    #[test]
+    fn load_overrides() {
+        let config = load(
+            MINIMAL,
+            vec![
+                ("station.name", "gb3pm".into()),
+                ("mqtt.qos", 2.into()),
+                ("rooms", vec!["!room:example.com"].into()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.station.name, "gb3pm");
+        assert_eq!(config.mqtt.qos, 2);
+        assert_eq!(config.rooms.len(), 1);
+    }
+
+    #[test]
+    fn load_brokers() {
+        let config = load(
+            &format!("{}\n[mqtt]\nbroker = \"tcp://a:1883\"", MINIMAL),
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(config.mqtt.brokers, vec!["tcp://a:1883"]);
+
+        let config = load(
+            MINIMAL,
+            vec![("mqtt.broker", vec!["tcp://a:1883", "tcp://b:1883"].into())],
+        )
+        .unwrap();
+        assert_eq!(config.mqtt.brokers, vec!["tcp://a:1883", "tcp://b:1883"]);
+
+        let empty: Vec<String> = Vec::new();
+        assert!(load(MINIMAL, vec![("mqtt.broker", empty.into())]).is_err());
+    }
+
+    #[test]
+    fn load_without_file() {
+        assert!(Config::load(None, vec![]).is_err());
+        assert!(Config::load(
+            None,
+            vec![
+                ("matrix.username", "@bot:example.com".into()),
+                ("matrix.password", "hunter2".into()),
+                ("matrix.storage", "/tmp".into()),
+                ("station.name", "mb7pmf".into()),
+                ("station.status_topic", "mb7pmf".into()),
+                ("station.command_topic", "mb7pmf/command".into()),
+            ]
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn load_invalid() {
+        assert!(load(&format!("{}\nunknown = 1", MINIMAL), vec![]).is_err());
+        assert!(load(
+            &format!("{}\n[templates]\nstatus = \"{{nope}}\"", MINIMAL),
+            vec![]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn load_console() {
+        let path = std::env::temp_dir().join(format!(
+            "matrix-remote-closedown-console-test-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"
+            [mqtt]
+            availability_topic = "bot/availability"
+
+            [station]
+            name = "mb7pmf"
+            status_topic = "mb7pmf"
+            command_topic = "mb7pmf/command"
+            "#,
+        )
+        .unwrap();
+
+        // No Matrix settings are needed, and availability is left to the bot
+        let config = Config::load_console(Some(&path), vec![]).unwrap();
+        assert_eq!(config.mqtt.availability_topic, None);
+        assert!(Config::load(Some(&path), vec![]).is_err());
+    }
+
+    #[test]
+    fn load_secrets() {
+        let password_file = std::env::temp_dir().join(format!(
+            "matrix-remote-closedown-config-test-secret-{}",
+            std::process::id()
+        ));
+        std::fs::write(&password_file, "from file\n").unwrap();
+        let password_file = toml::Value::from(password_file.display().to_string());
+
+        let config = load(
+            MINIMAL,
+            vec![("matrix.password_file", password_file.clone())],
+        )
+        .unwrap();
+        assert_eq!(config.matrix.password(), "from file");
+        assert_eq!(format!("{:?}", config).matches("<redacted>").count(), 1);
+
+        let config = load(MINIMAL, vec![("mqtt.password_file", password_file.clone())]).unwrap();
+        assert_eq!(config.mqtt.password(), "from file");
+
+        let both = format!(
+            "{}\n[mqtt]\npassword = \"x\"\npassword_file = {}",
+            MINIMAL, password_file
+        );
+        assert!(load(&both, vec![]).is_err());
+
+        let webhook = format!(
+            "{}\n[webhook]\nlisten_address = \"127.0.0.1:9091\"\nurl = \"http://localhost/hook\"",
+            MINIMAL
+        );
+        assert!(load(&webhook, vec![]).is_err());
+        let config = load(
+            &format!("{}\ntoken_file = {}", webhook, password_file),
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(config.webhook.unwrap().token.unwrap().expose(), "from file");
+
+        let api = format!(
+            "{}\n[api]\nlisten_address = \"127.0.0.1:9092\"\n[[api.clients]]\nuser = \"@website:api\"",
+            MINIMAL
+        );
+        assert!(load(&api, vec![]).is_err());
+        let config = load(&format!("{}\ntoken_file = {}", api, password_file), vec![]).unwrap();
+        let api = config.api.unwrap();
+        assert_eq!(api.clients[0].token(), "from file");
+        assert_eq!(api.history_size, 100);
+
+        assert!(load(&format!("{}\n[signing]", MINIMAL), vec![]).is_err());
+        let config = load(
+            &format!("{}\n[signing]\nkey_file = {}", MINIMAL, password_file),
+            vec![],
+        )
+        .unwrap();
+        let signing = config.signing.unwrap();
+        assert_eq!(signing.key.unwrap().expose(), "from file");
+        assert_eq!(signing.max_age, 30);
+    }
+
+    #[test]
+    fn load_link_config() {
+        let overrides = || {
+            vec![
+                ("station.name", "mb7pmf".into()),
+                ("station.status_topic", "mb7pmf".into()),
+                ("station.command_topic", "mb7pmf/command".into()),
+                ("matrix.username", "not checked".into()),
+            ]
+        };
+        assert!(LinkConfig::load(None, overrides()).is_ok());
+
+        let mut invalid = overrides();
+        invalid.push(("station.command_topic", "#".into()));
+        assert!(LinkConfig::load(None, invalid).is_err());
+
+        let mut cbor = overrides();
+        cbor.push(("station.encoding", "cbor".into()));
+        assert_eq!(
+            LinkConfig::load(None, cbor).unwrap().station.encoding,
+            Encoding::Cbor
+        );
+
+        let serial = || {
+            vec![
+                ("station.name", "mb7pmf".into()),
+                ("station.transport", "serial".into()),
+            ]
+        };
+        assert!(LinkConfig::load(None, serial()).is_err());
+        let mut serial = serial();
+        serial.push(("serial.port", "/dev/ttyUSB0".into()));
+        let config = LinkConfig::load(None, serial.clone()).unwrap();
+        assert_eq!(config.station.transport, TransportKind::Serial);
+        assert_eq!(config.serial.unwrap().baud_rate, 9600);
+        serial.push(("station.encoding", "msgpack".into()));
+        assert!(LinkConfig::load(None, serial).is_err());
+    }
+
+    #[test]
+    fn check_ok() {
+        let config = load(MINIMAL, vec![("rooms", vec!["!room:example.com"].into())]).unwrap();
+        assert_eq!(config.check(), vec![]);
+    }
+
+    #[test]
+    fn check_errors() {
+        let config = Config::load_unchecked(
+            None,
+            vec![
+                ("matrix.username", "@bot:example.com".into()),
+                ("matrix.password", "hunter2".into()),
+                ("matrix.storage", "/tmp".into()),
+                ("station.name", "mb7pmf".into()),
+                ("station.status_topic", "mb7pmf/#/status".into()),
+                ("station.command_topic", "mb7pmf/+".into()),
+                ("mqtt.qos", 3.into()),
+                (
+                    "commands.reactions",
+                    vec!["🛑=shutdown", "🛑=ptt disable"].into(),
+                ),
+                ("templates.status", "{nope}".into()),
+                ("status_buffer_size", 0.into()),
+            ],
+        )
+        .unwrap();
+
+        let issues = config.check();
+        assert_eq!(
+            issues
+                .iter()
+                .filter(|i| matches!(i, Issue::Error(_)))
+                .count(),
+            6
+        );
+        assert!(issues.contains(&Issue::Warning(
+            "no rooms are configured, status will not be posted anywhere".to_string()
+        )));
+    }
+
+    #[test]
+    fn check_mqtt_version() {
+        let mut config = load(MINIMAL, vec![("rooms", vec!["!room:example.com"].into())]).unwrap();
+
+        config.mqtt.version = 5;
+        assert_eq!(
+            config.check(),
+            vec![Issue::Error(
+                "station.response_topic must not be empty".to_string()
+            )]
+        );
+
+        config.station.response_topic = "mb7pmf/response".to_string();
+        assert_eq!(config.check(), vec![]);
+
+        config.mqtt.version = 4;
+        assert_eq!(config.check().len(), 1);
+    }
+
+    #[test]
+    fn check_relays() {
+        let relays = r#"
+            [[relays]]
+            topic = "mb7pmf/alarms/#"
+            template = "{station}: {text} ({severity})"
+            fields = { text = "/text" }
+            severity_field = "/level"
+            min_severity = "warning"
+        "#;
+        let config = load(
+            &format!("{}\n{}", MINIMAL, relays),
+            vec![("rooms", vec!["!room:example.com"].into())],
+        )
+        .unwrap();
+        assert_eq!(config.check(), vec![]);
+        assert_eq!(config.link().relay_topics, vec!["mb7pmf/alarms/#"]);
+        assert_eq!(config.relays[0].min_severity, Some(Severity::Warning));
+
+        let relay = RelayConfig {
+            topic: "mb7pmf/#/x".to_string(),
+            fields: [("text".to_string(), "text".to_string())].into(),
+            template: Template::new("{nope}"),
+            ..config.relays[0].clone()
+        };
+        assert_eq!(check_relay(0, &relay, &TransportKind::Mqtt).len(), 3);
+        assert_eq!(
+            check_relay(0, &config.relays[0], &TransportKind::Serial).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn check_channels() {
+        let channels = r#"
+            [[channels]]
+            name = "linear amp"
+            label = "Linear"
+            status_field = "amp_enabled"
+            command_field = "enable_amp"
+            shutdown = false
+        "#;
+        let config = load(
+            &format!("{}\n{}", MINIMAL, channels),
+            vec![
+                ("rooms", vec!["!room:example.com"].into()),
+                ("commands.reactions", vec!["📢=linear amp on"].into()),
+                ("templates.status", "{station}: {amp_enabled}".into()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.check(), vec![]);
+        assert_eq!(config.channels.len(), 1);
+        assert_eq!(config.channels[0].enable_word, "on");
+        assert_eq!(config.link().channels, config.channels);
+
+        let mut channels = default_channels();
+        channels[1].name = "power".to_string();
+        channels[1].status_field = "tx_power_active".to_string();
+        channels[1].disable_word = "enable".to_string();
+        assert_eq!(super::check_channels(&channels).len(), 3);
+
+        let err = load(
+            MINIMAL,
+            vec![("commands.reactions", vec!["📢=amp on"].into())],
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("Unknown command"));
+    }
+
+    #[test]
+    fn check_api() {
+        let api = |tokens: &[&str]| ApiConfig {
+            listen_address: "127.0.0.1:9092".parse().unwrap(),
+            history_size: 100,
+            clients: tokens
+                .iter()
+                .enumerate()
+                .map(|(i, token)| ApiClientConfig {
+                    user: format!("@client{}:api", i).try_into().unwrap(),
+                    token: Some(token.parse().unwrap()),
+                    token_file: None,
+                })
+                .collect(),
+        };
+
+        assert_eq!(
+            super::check_api(&api(&["0123456789abcdef", "fedcba9876543210"])),
+            vec![]
+        );
+        assert_eq!(super::check_api(&api(&[])).len(), 1);
+        // Two short tokens and a duplicate
+        assert_eq!(super::check_api(&api(&["hunter2", "hunter2"])).len(), 3);
+    }
+
+    #[test]
+    fn check_operations() {
+        let operations = r#"
+            [[operations]]
+            name = "beacon start"
+            topic = "mb7pmf/beacon"
+            payload = { mode = "cw", interval = 600 }
+
+            [[macros]]
+            name = "maintenance"
+            steps = ["ptt disable", "wait 5s", "power off"]
+        "#;
+        let config = load(
+            &format!("{}\n{}", MINIMAL, operations),
+            vec![
+                ("rooms", vec!["!room:example.com"].into()),
+                ("permissions.default", vec!["help", "maintenance"].into()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.check(), vec![]);
+        assert_eq!(
+            action::resolve(&config, &"Beacon Start".parse().unwrap()).unwrap(),
+            Action::Custom {
+                topic: Some("mb7pmf/beacon".to_string()),
+                payload: br#"{"interval":600,"mode":"cw"}"#.to_vec(),
+            }
+        );
+        assert_eq!(
+            action::resolve(&config, &"maintenance".parse().unwrap()).unwrap(),
+            Action::Macro(vec![
+                "ptt disable".parse().unwrap(),
+                Step::Wait(std::time::Duration::from_secs(5)),
+                "power off".parse().unwrap(),
+            ])
+        );
+
+        let mut config = config;
+        config.operations[0].name = "power on".to_string();
+        config.operations[0].topic = Some("mb7pmf/#".to_string());
+        config.macros[0].steps.push("maintenance".parse().unwrap());
+        config.macros[0].steps.push("beacon stop".parse().unwrap());
+        assert_eq!(
+            config
+                .check()
+                .iter()
+                .filter(|i| matches!(i, Issue::Error(_)))
+                .count(),
+            4
+        );
+    }
+
+    #[test]
+    fn check_fields() {
+        let fields = r#"
+            [[fields]]
+            name = "temperature"
+            label = "Temperature"
+            unit = "°C"
+            precision = 1
+            max = 60.0
+        "#;
+        let config = load(
+            &format!("{}\n{}", MINIMAL, fields),
+            vec![
+                ("rooms", vec!["!room:example.com"].into()),
+                (
+                    "templates.status",
+                    "{channels}<br>{fields} ({temperature})".into(),
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.check(), vec![]);
+
+        let mut config = config;
+        config.fields[0].min = Some(70.0);
+        config.fields.push(FieldConfig {
+            name: "ptt_active".to_string(),
+            ..config.fields[0].clone()
+        });
+        assert_eq!(config.check_fields().len(), 3);
+    }
+
+    #[test]
+    fn check_tls() {
+        let ca_file = std::env::temp_dir().join(format!(
+            "matrix-remote-closedown-ca-test-{}.pem",
+            std::process::id()
+        ));
+        std::fs::write(&ca_file, "").unwrap();
+        let tls = MqttTlsConfig {
+            ca_file: Some(ca_file),
+            cert_file: Some(std::env::temp_dir()),
+            key_file: Some("/nonexistent/client.key".into()),
+            verify_hostname: true,
+        };
+        let issues = check_mqtt_tls(&["tcp://localhost:1883".to_string()], &tls);
+        assert_eq!(issues.len(), 3);
+        assert!(issues.iter().all(|i| matches!(i, Issue::Error(_))));
+        assert!(issues.contains(&Issue::Error(format!(
+            "mqtt.tls.cert_file {} is not a file",
+            std::env::temp_dir().display()
+        ))));
+
+        let tls = MqttTlsConfig {
+            cert_file: None,
+            key_file: None,
+            ..tls
+        };
+        assert_eq!(
+            check_mqtt_tls(&["ssl://localhost:8883".to_string()], &tls),
+            Vec::new()
+        );
+
+        let tls = MqttTlsConfig {
+            ca_file: None,
+            cert_file: None,
+            key_file: None,
+            verify_hostname: false,
+        };
+        assert_eq!(
+            check_mqtt_tls(&["ssl://localhost:8883".to_string()], &tls),
+            vec![Issue::Warning(
+                "mqtt.tls.verify_hostname is disabled, the broker could be impersonated"
+                    .to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn topic_syntax() {
+        assert!(check_topic("a/b/c", false).is_ok());
+        assert!(check_topic("a/+/#", true).is_ok());
+        assert!(check_topic("#", true).is_ok());
+        assert!(check_topic("", true).is_err());
+        assert!(check_topic("a/+", false).is_err());
+        assert!(check_topic("a/b+", true).is_err());
+        assert!(check_topic("a/#/c", true).is_err());
+        assert!(check_topic("a/b#", true).is_err());
+    }
+
+    #[test]
+    fn permissions() {
+        let config = load(
+            &format!(
+                r#"{}
+                [permissions]
+                default = ["help"]
+                [permissions.users]
+                "@alice:example.com" = ["help", "shutdown", "ptt disable"]
+                "#,
+                MINIMAL
+            ),
+            vec![],
+        )
+        .unwrap();
+
+        let alice = <&UserId>::try_from("@alice:example.com").unwrap();
+        let bob = <&UserId>::try_from("@bob:example.com").unwrap();
+        assert!(config.permissions.allows(alice, &Operation::Shutdown));
+        assert!(!config
+            .permissions
+            .allows(alice, &"power on".parse().unwrap()));
+        assert!(config.permissions.allows(bob, &Operation::Help));
+        assert!(!config.permissions.allows(bob, &Operation::Shutdown));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::config::ConnectivityConfig;
+use chrono::{offset::Local, DateTime, Duration};
+use std::{collections::HashMap, fmt};
+
+/// A connection the bot depends on.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum Service {
+    Mqtt,
+    Matrix,
+}
+
+impl fmt::Display for Service {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mqtt => write!(f, "MQTT broker"),
+            Self::Matrix => write!(f, "Matrix"),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ServiceState {
+    lost_at: Option<DateTime<Local>>,
+    /// The loss of the current outage was announced, so its end should be too
+    announced: bool,
+    last_announcement: Option<DateTime<Local>>,
+}
+
+/// Decides which connection losses and recoveries are worth announcing, limiting how often
+/// announcements are made for each service.
+#[derive(Default)]
+pub(crate) struct Notices {
+    services: HashMap<Service, ServiceState>,
+}
+
+impl Notices {
+    /// Records a change in connectivity, returning the text of an announcement to make (if any).
+    pub(crate) fn update(
+        &mut self,
+        config: &ConnectivityConfig,
+        service: Service,
+        connected: bool,
+        at: DateTime<Local>,
+    ) -> Option<String> {
+        let state = self.services.entry(service).or_default();
+        let may_announce = config.announce
+            && state
+                .last_announcement
+                .is_none_or(|last| at - last >= Duration::seconds(config.min_interval as i64));
+
+        if connected {
+            let lost_at = state.lost_at.take()?;
+            let outage = format_duration(at - lost_at);
+            if std::mem::take(&mut state.announced) {
+                state.last_announcement = Some(at);
+                Some(format!("reconnected to {} after {}", service, outage))
+            } else if may_announce {
+                state.last_announcement = Some(at);
+                Some(format!(
+                    "lost connection to {} at {}, reconnected after {}",
+                    service,
+                    lost_at.format("%H:%M"),
+                    outage
+                ))
+            } else {
+                log::info!(
+                    "Not announcing reconnection to {} after {}",
+                    service,
+                    outage
+                );
+                None
+            }
+        } else {
+            if state.lost_at.is_some() {
+                return None;
+            }
+            state.lost_at = Some(at);
+            if may_announce {
+                state.announced = true;
+                state.last_announcement = Some(at);
+                Some(format!(
+                    "lost connection to {} at {}",
+                    service,
+                    at.format("%H:%M")
+                ))
+            } else {
+                log::info!("Not announcing lost connection to {}", service);
+                None
+            }
+        }
+    }
+}
+
+/// Formats a duration compactly, e.g. `4m12s`.
+fn format_duration(d: Duration) -> String {
+    let secs = d.num_seconds().max(0);
+    match (secs / 3600, secs % 3600 / 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{}s", m, s),
+        (h, m, _) => format!("{}h{}m", h, m),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::seconds(12)), "12s");
+        assert_eq!(format_duration(Duration::seconds(252)), "4m12s");
+        assert_eq!(format_duration(Duration::seconds(3900)), "1h5m");
+    }
+
+    #[test]
+    fn announces_outage() {
+        let config = ConnectivityConfig::default();
+        let mut notices = Notices::default();
+
+        assert_eq!(notices.update(&config, Service::Mqtt, true, at(0)), None);
+        assert_eq!(
+            notices.update(&config, Service::Mqtt, false, at(0)),
+            Some(format!(
+                "lost connection to MQTT broker at {}",
+                at(0).format("%H:%M")
+            ))
+        );
+        assert_eq!(notices.update(&config, Service::Mqtt, false, at(10)), None);
+        assert_eq!(
+            notices.update(&config, Service::Mqtt, true, at(252)),
+            Some("reconnected to MQTT broker after 4m12s".to_string())
+        );
+    }
+
+    #[test]
+    fn rate_limited() {
+        let config = ConnectivityConfig::default();
+        let mut notices = Notices::default();
+
+        assert!(notices
+            .update(&config, Service::Mqtt, false, at(0))
+            .is_some());
+        assert!(notices
+            .update(&config, Service::Mqtt, true, at(5))
+            .is_some());
+
+        // Flapping within the minimum interval is not announced
+        assert_eq!(notices.update(&config, Service::Mqtt, false, at(10)), None);
+        assert_eq!(notices.update(&config, Service::Mqtt, true, at(20)), None);
+
+        // Other services are limited separately
+        assert!(notices
+            .update(&config, Service::Matrix, false, at(20))
+            .is_some());
+
+        // An outage that was not announced is summarised once it ends, if allowed by then
+        assert_eq!(notices.update(&config, Service::Mqtt, false, at(30)), None);
+        assert_eq!(
+            notices.update(&config, Service::Mqtt, true, at(400)),
+            Some(format!(
+                "lost connection to MQTT broker at {}, reconnected after 6m10s",
+                at(30).format("%H:%M")
+            ))
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let config = ConnectivityConfig {
+            announce: false,
+            ..Default::default()
+        };
+        let mut notices = Notices::default();
+        assert_eq!(notices.update(&config, Service::Mqtt, false, at(0)), None);
+        assert_eq!(notices.update(&config, Service::Mqtt, true, at(400)), None);
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+
+/// How messages exchanged with the station are serialised.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Encoding {
+    #[default]
+    Json,
+    Cbor,
+    #[serde(rename = "msgpack")]
+    #[clap(name = "msgpack")]
+    MessagePack,
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Cbor => write!(f, "cbor"),
+            Self::MessagePack => write!(f, "msgpack"),
+        }
+    }
+}
+
+impl Encoding {
+    /// Guesses the encoding of a message from its first byte, which is unambiguous as every
+    /// message is a map (or object).
+    pub(crate) fn detect(payload: &[u8]) -> Option<Self> {
+        match payload {
+            // Map, or the self-described CBOR tag
+            [0xa0..=0xbf, ..] | [0xd9, 0xd9, 0xf7, ..] => Some(Self::Cbor),
+            // fixmap, map 16 or map 32
+            [0x80..=0x8f | 0xde | 0xdf, ..] => Some(Self::MessagePack),
+            _ => match payload.iter().find(|b| !b.is_ascii_whitespace()) {
+                Some(b'{') => Some(Self::Json),
+                _ => None,
+            },
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize>(self, value: &T) -> Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            Self::Cbor => {
+                let mut payload = Vec::new();
+                ciborium::ser::into_writer(value, &mut payload)?;
+                payload
+            }
+            // Maps with field names, rather than arrays, so that fields can be added or omitted
+            Self::MessagePack => rmp_serde::to_vec_named(value)?,
+        })
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(self, payload: &[u8]) -> Result<T> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(payload)?,
+            Self::Cbor => ciborium::de::from_reader(payload)?,
+            Self::MessagePack => rmp_serde::from_slice(payload)?,
+        })
+    }
+}
+
+/// Decodes a message received from the station, in whichever encoding it appears to be in or
+/// `expected` if that cannot be told.
+pub(crate) fn decode<T: DeserializeOwned>(payload: &[u8], expected: Encoding) -> Result<T> {
+    let encoding = Encoding::detect(payload).unwrap_or(expected);
+    if encoding != expected {
+        log::debug!("Received {} message, expected {}", encoding, expected);
+    }
+    encoding.decode(payload)
+}
+
+/// A payload in a form suitable for logging, as text if it is or hex otherwise.
+pub(crate) fn describe(payload: &[u8]) -> String {
+    match std::str::from_utf8(payload) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("0x{}", hex::encode(payload)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{Command, Response};
+
+    #[test]
+    fn round_trip() {
+        let response = r#"{"version": 2, "status": {"tx_power_enabled": true, "temperature": 21.5}, "message": null, "timestamp": "2024-01-01T00:00:00Z"}"#;
+        let response: Response = serde_json::from_str(response).unwrap();
+        let cmd = Command {
+            version: Some(2),
+            fields: [
+                ("enable_ptt".to_string(), None),
+                ("enable_tx_power".to_string(), Some(false)),
+            ]
+            .into(),
+        };
+
+        for encoding in [Encoding::Json, Encoding::Cbor, Encoding::MessagePack] {
+            let payload = encoding.encode(&response).unwrap();
+            assert_eq!(Encoding::detect(&payload), Some(encoding));
+            let decoded: Response = decode(&payload, Encoding::Json).unwrap();
+            assert_eq!(decoded.status, response.status);
+            assert_eq!(decoded.timestamp, response.timestamp);
+
+            let payload = encoding.encode(&cmd).unwrap();
+            assert_eq!(Encoding::detect(&payload), Some(encoding));
+            assert_eq!(decode::<Command>(&payload, Encoding::Json).unwrap(), cmd);
+        }
+
+        // CBOR is smaller than the equivalent JSON
+        assert!(
+            Encoding::Cbor.encode(&cmd).unwrap().len() < Encoding::Json.encode(&cmd).unwrap().len()
+        );
+    }
+
+    #[test]
+    fn detect() {
+        assert_eq!(Encoding::detect(b" \n{}"), Some(Encoding::Json));
+        assert_eq!(Encoding::detect(b""), None);
+        assert_eq!(Encoding::detect(b"garbage"), None);
+        assert_eq!(describe(b"{}"), "{}");
+        assert_eq!(describe(&[0xa1, 0xff]), "0xa1ff");
+    }
+}
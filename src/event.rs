@@ -1,15 +1,30 @@
-use crate::command::Command;
-use anyhow::Error;
-use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+use crate::{
+    command::{Command, Operation},
+    config::Config,
+    connectivity::Service,
+    frontend::Origin,
+};
+use chrono::{offset::Local, DateTime};
+use matrix_sdk::ruma::{OwnedEventId, OwnedRoomId, OwnedUserId};
 
 #[derive(Clone, Debug)]
 pub(crate) enum Event {
     MatrixMessageReceive(MatrixMessageReceiveEvent),
+    MatrixReactionReceive(MatrixReactionReceiveEvent),
 
-    MqttStatusMessageReceived(String),
-    MqttSendCommandMessage(String),
+    MessageReceive(MessageReceiveEvent),
+
+    StatusMessageReceived(Vec<u8>),
+    CommandReplyReceived(CommandReplyEvent),
+    SendCommandMessage(CommandMessage),
+    BrokerChanged(BrokerChangedEvent),
+    ConnectivityChanged(ConnectivityEvent),
+    RelayMessageReceived(RelayMessageEvent),
 
     CommandReceive(CommandEvent),
+    MacroStep(MacroStepEvent),
+
+    ConfigReload(Box<Config>),
 
     Exit,
 }
@@ -17,23 +32,98 @@ pub(crate) enum Event {
 #[derive(Clone, Debug)]
 pub(crate) struct MatrixMessageReceiveEvent {
     pub room: OwnedRoomId,
+    pub event_id: OwnedEventId,
+    /// Root of the thread the message was sent in, if any
+    pub thread_root: Option<OwnedEventId>,
     pub sender: OwnedUserId,
+    pub timestamp: DateTime<Local>,
     pub body: String,
+    pub formatted_body: Option<String>,
 }
 
 #[derive(Clone, Debug)]
-pub(crate) struct CommandEvent {
+pub(crate) struct MatrixReactionReceiveEvent {
     pub room: OwnedRoomId,
+    pub sender: OwnedUserId,
+    pub timestamp: DateTime<Local>,
+    pub relates_to: OwnedEventId,
+    pub key: String,
+}
+
+/// A message received by a frontend that needs no frontend specific processing.
+#[derive(Clone, Debug)]
+pub(crate) struct MessageReceiveEvent {
+    pub origin: Origin,
+    pub sender: OwnedUserId,
+    pub body: String,
+    /// The message was sent directly to the bot, so the `!station` marker is implied
+    pub addressed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct CommandEvent {
+    pub origin: Origin,
+    pub sender: OwnedUserId,
     pub cmd: Command,
 }
 
-impl TryFrom<MatrixMessageReceiveEvent> for CommandEvent {
-    type Error = Error;
+/// The next operation of a running macro is due, it was permitted when the macro was requested.
+#[derive(Clone, Debug)]
+pub(crate) struct MacroStepEvent {
+    pub origin: Origin,
+    pub sender: OwnedUserId,
+    /// Name of the macro
+    pub name: String,
+    pub op: Operation,
+}
+
+/// The MQTT transport has switched to a different broker.
+#[derive(Clone, Debug)]
+pub(crate) struct BrokerChangedEvent {
+    pub from: String,
+    pub to: String,
+    /// Switched back to a more preferred broker, rather than away from an unreachable one
+    pub returned: bool,
+}
+
+/// A (serialised) command message to send to the station.
+#[derive(Clone, Debug)]
+pub(crate) struct CommandMessage {
+    /// Identifies replies to this command, if the transport supports them
+    pub correlation_id: String,
+    /// Topic to send to instead of the command topic
+    pub topic: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+/// A reply from the station to a specific command.
+#[derive(Clone, Debug)]
+pub(crate) struct CommandReplyEvent {
+    pub correlation_id: String,
+    pub payload: Vec<u8>,
+}
+
+/// A message received on a topic that is relayed to rooms.
+#[derive(Clone, Debug)]
+pub(crate) struct RelayMessageEvent {
+    pub topic: String,
+    pub payload: String,
+}
+
+/// The bot has lost or regained its connection to a service.
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectivityEvent {
+    pub service: Service,
+    pub connected: bool,
+    pub timestamp: DateTime<Local>,
+}
 
-    fn try_from(evt: MatrixMessageReceiveEvent) -> Result<Self, Self::Error> {
-        Ok(CommandEvent {
-            room: evt.room,
-            cmd: evt.body.try_into()?,
-        })
+impl ConnectivityEvent {
+    pub(crate) fn now(service: Service, connected: bool) -> Self {
+        Self {
+            service,
+            connected,
+            timestamp: Local::now(),
+        }
     }
 }
@@ -0,0 +1,196 @@
+use crate::{config::FieldConfig, schema::Status};
+use std::collections::HashSet;
+
+/// Shown for fields the station has not reported.
+const UNKNOWN: &str = "unknown";
+
+/// Formats the value of a field using its formatting rules, e.g. `21.5 °C`.
+pub(crate) fn format_value(field: &FieldConfig, status: &Status) -> String {
+    let value = match status.0.get(&field.name) {
+        Some(serde_json::Value::Number(n)) => match (field.precision, n.as_f64()) {
+            (Some(precision), Some(v)) => format!("{:.*}", precision, v),
+            _ => n.to_string(),
+        },
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(serde_json::Value::Null) | None => return UNKNOWN.to_string(),
+        Some(v) => v.to_string(),
+    };
+    with_unit(field, value)
+}
+
+fn format_threshold(field: &FieldConfig, value: f64) -> String {
+    let value = match field.precision {
+        Some(precision) => format!("{:.*}", precision, value),
+        None => value.to_string(),
+    };
+    with_unit(field, value)
+}
+
+fn with_unit(field: &FieldConfig, value: String) -> String {
+    if field.unit.is_empty() {
+        value
+    } else {
+        format!("{} {}", value, field.unit)
+    }
+}
+
+/// Formatted values of each field, keyed by field name.
+pub(crate) fn values<'a>(fields: &'a [FieldConfig], status: &Status) -> Vec<(&'a str, String)> {
+    fields
+        .iter()
+        .map(|f| (f.name.as_str(), format_value(f, status)))
+        .collect()
+}
+
+/// A line per field, e.g. `Temperature: 21.5 °C`.
+pub(crate) fn summary(fields: &[FieldConfig], status: &Status) -> String {
+    fields
+        .iter()
+        .map(|f| format!("{}: {}", f.label(), format_value(f, status)))
+        .collect::<Vec<_>>()
+        .join("<br>\n")
+}
+
+/// Configured fields that have a numeric value in a status report.
+pub(crate) fn numeric<'a>(
+    fields: &'a [FieldConfig],
+    status: &'a Status,
+) -> impl Iterator<Item = (&'a str, f64)> {
+    fields
+        .iter()
+        .filter_map(|field| Some((field.name.as_str(), status.0.get(&field.name)?.as_f64()?)))
+}
+
+/// Tracks which fields are outside of their thresholds, so that an alert is raised once when a
+/// field leaves its normal range and once when it returns.
+#[derive(Default)]
+pub(crate) struct Alerts {
+    breached: HashSet<String>,
+}
+
+impl Alerts {
+    /// Checks a status report against the thresholds, returning the text of any alerts to post.
+    pub(crate) fn update(
+        &mut self,
+        station: &str,
+        fields: &[FieldConfig],
+        status: &Status,
+    ) -> Vec<String> {
+        let mut alerts = Vec::new();
+
+        for field in fields {
+            let Some(value) = status.0.get(&field.name).and_then(|v| v.as_f64()) else {
+                continue;
+            };
+
+            let breach = match (field.min, field.max) {
+                (Some(min), _) if value < min => Some(format!(
+                    "below the minimum of {}",
+                    format_threshold(field, min)
+                )),
+                (_, Some(max)) if value > max => Some(format!(
+                    "above the maximum of {}",
+                    format_threshold(field, max)
+                )),
+                _ => None,
+            };
+
+            match breach {
+                Some(breach) => {
+                    if self.breached.insert(field.name.clone()) {
+                        alerts.push(format!(
+                            "**{}** {} is {}, {}",
+                            station,
+                            field.label(),
+                            format_value(field, status),
+                            breach
+                        ));
+                    }
+                }
+                None => {
+                    if self.breached.remove(&field.name) {
+                        alerts.push(format!(
+                            "**{}** {} is back to normal at {}",
+                            station,
+                            field.label(),
+                            format_value(field, status)
+                        ));
+                    }
+                }
+            }
+        }
+
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temperature() -> FieldConfig {
+        FieldConfig {
+            name: "temperature".to_string(),
+            label: Some("Temperature".to_string()),
+            unit: "°C".to_string(),
+            precision: Some(1),
+            min: None,
+            max: Some(60.0),
+        }
+    }
+
+    fn status(json: &str) -> Status {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn formats_values() {
+        let fields = [
+            temperature(),
+            FieldConfig {
+                name: "firmware".to_string(),
+                label: None,
+                unit: String::new(),
+                precision: None,
+                min: None,
+                max: None,
+            },
+        ];
+        let status = status(r#"{"temperature": 21.46, "firmware": "1.2.0", "swr": 1.3}"#);
+
+        assert_eq!(
+            summary(&fields, &status),
+            "Temperature: 21.5 °C<br>\nfirmware: 1.2.0"
+        );
+        assert_eq!(format_value(&fields[0], &Status::default()), "unknown");
+        assert_eq!(
+            numeric(&fields, &status).collect::<Vec<_>>(),
+            [("temperature", 21.46)]
+        );
+    }
+
+    #[test]
+    fn alerts_on_thresholds() {
+        let fields = [temperature()];
+        let mut alerts = Alerts::default();
+
+        assert!(alerts
+            .update("mb7pmf", &fields, &status(r#"{"temperature": 40}"#))
+            .is_empty());
+        assert_eq!(
+            alerts.update("mb7pmf", &fields, &status(r#"{"temperature": 65.3}"#)),
+            ["**mb7pmf** Temperature is 65.3 °C, above the maximum of 60.0 °C"]
+        );
+        assert!(alerts
+            .update("mb7pmf", &fields, &status(r#"{"temperature": 70}"#))
+            .is_empty());
+        // Missing values leave the alert as it was
+        assert!(alerts
+            .update("mb7pmf", &fields, &Status::default())
+            .is_empty());
+        assert_eq!(
+            alerts.update("mb7pmf", &fields, &status(r#"{"temperature": 50}"#)),
+            ["**mb7pmf** Temperature is back to normal at 50.0 °C"]
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use super::{read_body, Frontend, MessageRef, Origin, Refusal};
+use crate::{
+    config::{ApiConfig, StationConfig},
+    encoding::Encoding,
+    event::{Event, MessageReceiveEvent},
+    schema::{self, Status},
+    secret,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{offset::Local, DateTime};
+use hyper::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use matrix_sdk::ruma::OwnedUserId;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    oneshot,
+};
+
+pub(crate) const NAME: &str = "api";
+
+/// Maximum time to wait for the reply to a command request
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A status report from the station, as returned by the status and history endpoints.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+struct StatusReport {
+    timestamp: DateTime<Local>,
+    status: Status,
+    message: Option<String>,
+}
+
+/// A command request posted to the commands endpoint.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommandRequest {
+    /// Command as it would be given in Matrix, without the `!station` marker (e.g. `power off`)
+    command: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CommandResponse {
+    id: String,
+    reply: String,
+}
+
+/// State shared between the HTTP server and the frontend.
+struct State {
+    station: String,
+    /// Tokens and the users whose permissions apply to requests presenting them
+    clients: Vec<(String, OwnedUserId)>,
+    history_size: usize,
+    history: Mutex<VecDeque<StatusReport>>,
+    /// Command requests waiting for their reply and its HTTP status, by request ID
+    pending: Mutex<HashMap<String, oneshot::Sender<(StatusCode, String)>>>,
+    next_id: AtomicUsize,
+    tx: broadcast::Sender<Event>,
+}
+
+impl State {
+    fn new(config: &ApiConfig, station: &str, tx: broadcast::Sender<Event>) -> Self {
+        Self {
+            station: station.to_string(),
+            clients: config
+                .clients
+                .iter()
+                .map(|c| (c.token().to_string(), c.user.clone()))
+                .collect(),
+            history_size: config.history_size,
+            history: Mutex::new(VecDeque::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicUsize::new(0),
+            tx,
+        }
+    }
+
+    /// The user a request acts as, if it presents a known token.
+    fn authenticate(&self, header: Option<&str>) -> Option<OwnedUserId> {
+        let token = header?.strip_prefix("Bearer ")?;
+        self.clients
+            .iter()
+            .find(|(t, _)| secret::token_matches(t, token))
+            .map(|(_, user)| user.clone())
+    }
+
+    /// Completes the command request with the given ID.
+    fn respond(&self, id: &str, status: StatusCode, body: &str) {
+        // Only the first reply is returned, later ones (e.g. from macro steps) are in the log
+        match self.pending.lock().unwrap().remove(id) {
+            Some(reply) => {
+                if reply.send((status, body.to_string())).is_err() {
+                    log::debug!("API request {} has gone away", id);
+                }
+            }
+            None => log::debug!("No API request {} waiting for: {}", id, body),
+        }
+    }
+
+    fn record(&self, report: StatusReport) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() >= self.history_size {
+            history.pop_front();
+        }
+        history.push_back(report);
+    }
+}
+
+/// Serves the station's status and accepts command requests over HTTP, for integrations that do
+/// not use Matrix (e.g. websites and automation scripts).
+///
+/// Each client has a token and acts as a user, so commands are subject to the same permissions
+/// and logging as those from Matrix.
+pub(crate) struct ApiFrontend {
+    state: Arc<State>,
+}
+
+impl ApiFrontend {
+    /// Starts listening for requests and keeping the history of status reports.
+    pub(crate) fn start(
+        config: &ApiConfig,
+        station: &StationConfig,
+        tx: broadcast::Sender<Event>,
+    ) -> Result<Self> {
+        let state = Arc::new(State::new(config, &station.name, tx.clone()));
+
+        tokio::spawn(track_status(
+            state.clone(),
+            tx.subscribe(),
+            station.encoding,
+        ));
+
+        let service_state = state.clone();
+        let make_service = make_service_fn(move |_| {
+            let state = service_state.clone();
+            let service = service_fn(move |req| handle_request(req, state.clone()));
+            async move { Ok::<_, Infallible>(service) }
+        });
+        let server = Server::try_bind(&config.listen_address)?.serve(make_service);
+        log::info!("API listening on {}", config.listen_address);
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("API server failed ({})", e);
+            }
+        });
+
+        Ok(Self { state })
+    }
+}
+
+#[async_trait]
+impl Frontend for ApiFrontend {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn reply(&self, origin: &Origin, body: &str) -> Result<MessageRef> {
+        self.state.respond(&origin.channel, StatusCode::OK, body);
+        Ok(message_ref(origin))
+    }
+
+    async fn refuse(&self, origin: &Origin, body: &str, reason: Refusal) -> Result<MessageRef> {
+        let status = match reason {
+            Refusal::Denied => StatusCode::FORBIDDEN,
+            Refusal::Invalid => StatusCode::BAD_REQUEST,
+        };
+        self.state.respond(&origin.channel, status, body);
+        Ok(message_ref(origin))
+    }
+
+    async fn broadcast(&self, _body: &str) -> Result<Vec<MessageRef>> {
+        // Clients fetch the status when they want it
+        Ok(Vec::new())
+    }
+
+    async fn edit(&self, _message: &MessageRef, _body: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The reply to a command request, identified by the request ID.
+fn message_ref(origin: &Origin) -> MessageRef {
+    MessageRef {
+        frontend: NAME,
+        channel: origin.channel.clone(),
+        id: origin.channel.clone(),
+    }
+}
+
+/// Keeps the history of status reports from the station.
+async fn track_status(state: Arc<State>, mut rx: broadcast::Receiver<Event>, encoding: Encoding) {
+    loop {
+        match rx.recv().await {
+            Ok(Event::StatusMessageReceived(payload)) => {
+                // Failures are logged by the processing task
+                if let Ok(response) = schema::Response::parse(&payload, encoding) {
+                    state.record(StatusReport {
+                        timestamp: response.timestamp,
+                        status: response.status,
+                        message: response.message,
+                    });
+                }
+            }
+            Ok(Event::Exit) | Err(RecvError::Closed) => return,
+            Ok(_) | Err(RecvError::Lagged(_)) => {}
+        }
+    }
+}
+
+/// The station and endpoint a path refers to, e.g. `/stations/mb7pmf/status`.
+fn route(path: &str) -> Option<(&str, &str)> {
+    match path
+        .trim_matches('/')
+        .split('/')
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["stations", station, endpoint] => Some((*station, *endpoint)),
+        _ => None,
+    }
+}
+
+async fn handle_request(
+    req: Request<Body>,
+    state: Arc<State>,
+) -> Result<Response<Body>, Infallible> {
+    let Some((station, endpoint)) = route(req.uri().path()) else {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    };
+    if station != state.station {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    }
+    let endpoint = endpoint.to_string();
+
+    let auth = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    let Some(user) = state.authenticate(auth) else {
+        log::warn!("Rejected API request with missing or incorrect token");
+        return Ok(status_response(StatusCode::UNAUTHORIZED));
+    };
+
+    let method = req.method().clone();
+    Ok(match (method, endpoint.as_str()) {
+        (Method::GET, "status") => status(&state),
+        (Method::GET, "history") => history(&state),
+        (Method::POST, "commands") => match read_body(req.into_body()).await {
+            Ok(body) => match serde_json::from_slice::<CommandRequest>(&body) {
+                Ok(request) => command(&state, user, request).await,
+                Err(e) => {
+                    log::warn!("Failed to parse API command request ({})", e);
+                    text_response(StatusCode::BAD_REQUEST, &e.to_string())
+                }
+            },
+            Err(status) => status_response(status),
+        },
+        (_, "status" | "history" | "commands") => status_response(StatusCode::METHOD_NOT_ALLOWED),
+        _ => status_response(StatusCode::NOT_FOUND),
+    })
+}
+
+fn status(state: &State) -> Response<Body> {
+    match state.history.lock().unwrap().back() {
+        Some(report) => json_response(StatusCode::OK, report),
+        None => text_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "No status has been received from the station yet",
+        ),
+    }
+}
+
+fn history(state: &State) -> Response<Body> {
+    json_response(StatusCode::OK, &*state.history.lock().unwrap())
+}
+
+/// Passes a command request on as if it were a message, returning the reply to it (with a 403
+/// status if the user is not permitted to use the command, or 400 if it is not valid).
+async fn command(state: &State, user: OwnedUserId, request: CommandRequest) -> Response<Body> {
+    let id = state.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+    let (reply_tx, reply_rx) = oneshot::channel();
+    state.pending.lock().unwrap().insert(id.clone(), reply_tx);
+
+    crate::send_event!(
+        state.tx,
+        Event::MessageReceive(MessageReceiveEvent {
+            origin: Origin {
+                frontend: NAME,
+                channel: id.clone(),
+                message: None,
+                thread: None,
+            },
+            sender: user,
+            body: format!("!{} {}", state.station, request.command.trim()),
+            addressed: false,
+        })
+    );
+
+    match tokio::time::timeout(REPLY_TIMEOUT, reply_rx).await {
+        Ok(Ok((status, reply))) => json_response(status, &CommandResponse { id, reply }),
+        _ => {
+            state.pending.lock().unwrap().remove(&id);
+            status_response(StatusCode::GATEWAY_TIMEOUT)
+        }
+    }
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    match serde_json::to_vec(body) {
+        Ok(body) => Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap(),
+        Err(e) => {
+            log::error!("Failed to serialise API response because {}", e);
+            status_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn text_response(status: StatusCode, body: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ApiClientConfig;
+
+    fn state(history_size: usize) -> State {
+        let config = ApiConfig {
+            listen_address: "127.0.0.1:9092".parse().unwrap(),
+            history_size,
+            clients: vec![ApiClientConfig {
+                user: "@website:api".try_into().unwrap(),
+                token: Some("hunter2".parse().unwrap()),
+                token_file: None,
+            }],
+        };
+        State::new(&config, "mb7pmf", broadcast::channel(1).0)
+    }
+
+    #[test]
+    fn authentication() {
+        let state = state(10);
+        assert_eq!(
+            state.authenticate(Some("Bearer hunter2")).unwrap().as_str(),
+            "@website:api"
+        );
+        assert_eq!(state.authenticate(Some("Bearer hunter3")), None);
+        assert_eq!(state.authenticate(Some("hunter2")), None);
+        assert_eq!(state.authenticate(None), None);
+    }
+
+    #[test]
+    fn routes() {
+        assert_eq!(route("/stations/mb7pmf/status"), Some(("mb7pmf", "status")));
+        assert_eq!(
+            route("/stations/mb7pmf/commands/"),
+            Some(("mb7pmf", "commands"))
+        );
+        assert_eq!(route("/stations/mb7pmf"), None);
+        assert_eq!(route("/metrics"), None);
+    }
+
+    #[test]
+    fn responses() {
+        let state = state(10);
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        state
+            .pending
+            .lock()
+            .unwrap()
+            .insert("0".to_string(), reply_tx);
+
+        state.respond("0", StatusCode::FORBIDDEN, "Not permitted");
+        // Only the first reply is returned
+        state.respond("0", StatusCode::OK, "Later");
+        assert_eq!(
+            reply_rx.try_recv().unwrap(),
+            (StatusCode::FORBIDDEN, "Not permitted".to_string())
+        );
+        assert!(state.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let state = state(2);
+        for i in 0..3 {
+            state.record(StatusReport {
+                timestamp: Local::now(),
+                status: Status::default(),
+                message: Some(i.to_string()),
+            });
+        }
+        let history = state.history.lock().unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|r| r.message.as_deref().unwrap())
+                .collect::<Vec<_>>(),
+            ["1", "2"]
+        );
+    }
+}
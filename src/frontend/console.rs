@@ -0,0 +1,113 @@
+use super::{Frontend, MessageRef, Origin};
+use crate::event::{Event, MessageReceiveEvent};
+use anyhow::Result;
+use async_trait::async_trait;
+use matrix_sdk::ruma::OwnedUserId;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    sync::broadcast,
+    task::JoinHandle,
+};
+
+pub(crate) const NAME: &str = "console";
+
+/// Commands typed on stdin, with responses and status notifications printed to stdout.
+#[derive(Default)]
+pub(crate) struct ConsoleFrontend {
+    next_id: AtomicUsize,
+}
+
+impl ConsoleFrontend {
+    /// Starts reading commands from stdin, which are sent as events to `tx` on behalf of `user`.
+    ///
+    /// The returned task finishes when stdin is closed.
+    pub(crate) fn start(tx: broadcast::Sender<Event>, user: OwnedUserId) -> (Self, JoinHandle<()>) {
+        let task = tokio::spawn(async move {
+            let mut lines = BufReader::new(tokio::io::stdin()).lines();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => {
+                        if !line.trim().is_empty() {
+                            crate::send_event!(
+                                tx,
+                                Event::MessageReceive(MessageReceiveEvent {
+                                    origin: origin(),
+                                    sender: user.clone(),
+                                    body: line,
+                                    addressed: true,
+                                })
+                            );
+                        }
+                    }
+                    Ok(None) => return,
+                    Err(e) => {
+                        log::error!("Failed to read from stdin ({})", e);
+                        return;
+                    }
+                }
+            }
+        });
+
+        (Self::default(), task)
+    }
+
+    fn print(&self, body: &str) -> MessageRef {
+        println!("{}", to_plain_text(body));
+
+        MessageRef {
+            frontend: NAME,
+            channel: NAME.to_string(),
+            id: self.next_id.fetch_add(1, Ordering::Relaxed).to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl Frontend for ConsoleFrontend {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn reply(&self, _origin: &Origin, body: &str) -> Result<MessageRef> {
+        Ok(self.print(body))
+    }
+
+    async fn broadcast(&self, body: &str) -> Result<Vec<MessageRef>> {
+        Ok(vec![self.print(body)])
+    }
+
+    async fn edit(&self, _message: &MessageRef, body: &str) -> Result<()> {
+        self.print(body);
+        Ok(())
+    }
+}
+
+fn origin() -> Origin {
+    Origin {
+        frontend: NAME,
+        channel: NAME.to_string(),
+        message: None,
+        thread: None,
+    }
+}
+
+/// Removes the Markdown/HTML formatting used in messages that would be noise on a terminal.
+fn to_plain_text(body: &str) -> String {
+    body.replace("<br>", "\n")
+        .replace("**", "")
+        .replace('`', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(
+            to_plain_text("Sending `power off` to **mb7pmf**<br>done"),
+            "Sending power off to mb7pmf\ndone"
+        );
+    }
+}
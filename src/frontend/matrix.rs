@@ -0,0 +1,256 @@
+use super::{Frontend, MessageRef, Origin};
+use crate::{
+    config::Config,
+    connectivity::Service,
+    event::{ConnectivityEvent, Event, MatrixMessageReceiveEvent, MatrixReactionReceiveEvent},
+};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use chrono::{offset::Local, DateTime};
+use matrix_sdk::{
+    event_handler::Ctx,
+    room::Room,
+    ruma::{
+        events::{
+            reaction::OriginalSyncReactionEvent,
+            room::message::{
+                InReplyTo, MessageFormat, MessageType, OriginalSyncRoomMessageEvent, Relation,
+                Replacement, RoomMessageEventContent, TextMessageEventContent, Thread,
+            },
+        },
+        EventId, MilliSecondsSinceUnixEpoch, OwnedEventId, OwnedRoomId, RoomId,
+    },
+};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+pub(crate) const NAME: &str = "matrix";
+
+/// How often the homeserver is checked to be reachable
+const PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum time to wait for the homeserver to respond to a check
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) struct MatrixFrontend {
+    client: matrix_client_boilerplate::Client,
+    rooms: Vec<OwnedRoomId>,
+}
+
+impl MatrixFrontend {
+    /// Logs in and starts receiving messages, which are sent as events to `tx`.
+    pub(crate) async fn login(config: &Config, tx: broadcast::Sender<Event>) -> Result<Self> {
+        let client = matrix_client_boilerplate::Client::new(
+            config.matrix.username.as_str(),
+            config.matrix.password(),
+            "matrix-remote-closedown",
+            &config.matrix.storage,
+        )
+        .await?;
+        client.initial_sync().await?;
+
+        client.client().add_event_handler_context(tx.clone());
+        client.client().add_event_handler(on_room_message);
+        client.client().add_event_handler(on_reaction);
+
+        client.start_background_sync().await;
+        tokio::spawn(probe(client.client().clone(), tx));
+
+        Ok(Self {
+            client,
+            rooms: config.rooms.clone(),
+        })
+    }
+
+    async fn send(&self, room: &RoomId, content: RoomMessageEventContent) -> Result<MessageRef> {
+        let event_id = self
+            .client
+            .client()
+            .get_joined_room(room)
+            .ok_or_else(|| anyhow!("Not joined to room {}", room))?
+            .send(content, None)
+            .await?
+            .event_id;
+
+        Ok(MessageRef {
+            frontend: NAME,
+            channel: room.to_string(),
+            id: event_id.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Frontend for MatrixFrontend {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    fn reload(&mut self, config: &Config) {
+        self.rooms = config.rooms.clone();
+    }
+
+    async fn reply(&self, origin: &Origin, body: &str) -> Result<MessageRef> {
+        let room = RoomId::parse(&origin.channel)?;
+        let respond_to = match &origin.message {
+            Some(event_id) => Some(ResponseTarget {
+                event_id: EventId::parse(event_id)?,
+                thread_root: origin.thread.as_deref().map(EventId::parse).transpose()?,
+            }),
+            None => None,
+        };
+
+        let mut content = RoomMessageEventContent::text_markdown(body);
+        content.relates_to = respond_to.as_ref().map(ResponseTarget::relation);
+        self.send(&room, content).await
+    }
+
+    async fn broadcast(&self, body: &str) -> Result<Vec<MessageRef>> {
+        let mut ids = Vec::new();
+        for room in &self.rooms {
+            ids.push(
+                self.send(room, RoomMessageEventContent::text_markdown(body))
+                    .await?,
+            );
+        }
+        Ok(ids)
+    }
+
+    async fn edit(&self, message: &MessageRef, body: &str) -> Result<()> {
+        let room = RoomId::parse(&message.channel)?;
+
+        // Clients that do not support edits show the fallback, marked as a correction
+        let mut content = RoomMessageEventContent::text_markdown(format!("* {}", body));
+        content.relates_to = Some(Relation::Replacement(Replacement::new(
+            EventId::parse(&message.id)?,
+            Box::new(RoomMessageEventContent::text_markdown(body)),
+        )));
+        self.send(&room, content).await.map(|_| ())
+    }
+}
+
+/// Periodically checks that the homeserver is reachable, sending an event when that changes.
+async fn probe(client: matrix_sdk::Client, tx: broadcast::Sender<Event>) {
+    let mut connected = true;
+    let mut interval = tokio::time::interval(PROBE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let reachable = match tokio::time::timeout(PROBE_TIMEOUT, client.whoami()).await {
+            Ok(Ok(_)) => true,
+            Ok(Err(e)) => {
+                log::debug!("Matrix check failed ({})", e);
+                false
+            }
+            Err(_) => {
+                log::debug!("Matrix check timed out");
+                false
+            }
+        };
+
+        if reachable != connected {
+            connected = reachable;
+            crate::send_event!(
+                tx,
+                Event::ConnectivityChanged(ConnectivityEvent::now(Service::Matrix, connected))
+            );
+        }
+    }
+}
+
+/// Identifies the event that caused a command, so that responses can be related to it.
+#[derive(Clone, Debug)]
+pub(crate) struct ResponseTarget {
+    pub event_id: OwnedEventId,
+    pub thread_root: Option<OwnedEventId>,
+}
+
+impl ResponseTarget {
+    /// Responses are posted in the thread the command came from, or as a reply to the command
+    /// if it did not come from a thread.
+    pub(crate) fn relation(&self) -> Relation {
+        match &self.thread_root {
+            Some(root) if *root == self.event_id => {
+                Relation::Thread(Thread::plain(root.clone(), root.clone()))
+            }
+            Some(root) => Relation::Thread(Thread::reply(root.clone(), self.event_id.clone())),
+            None => Relation::Reply {
+                in_reply_to: InReplyTo::new(self.event_id.clone()),
+            },
+        }
+    }
+
+    pub(crate) fn origin(&self, room: &RoomId) -> Origin {
+        Origin {
+            frontend: NAME,
+            channel: room.to_string(),
+            message: Some(self.event_id.to_string()),
+            thread: self.thread_root.as_ref().map(|id| id.to_string()),
+        }
+    }
+}
+
+async fn on_room_message(
+    event: OriginalSyncRoomMessageEvent,
+    room: Room,
+    tx: Ctx<broadcast::Sender<Event>>,
+) {
+    if let Room::Joined(room) = room {
+        if let MessageType::Text(TextMessageEventContent {
+            body, formatted, ..
+        }) = event.content.msgtype
+        {
+            log::debug!("Received message in room {}", room.room_id());
+
+            crate::send_event!(
+                tx,
+                Event::MatrixMessageReceive(MatrixMessageReceiveEvent {
+                    room: room.room_id().into(),
+                    event_id: event.event_id.clone(),
+                    thread_root: match event.content.relates_to {
+                        Some(Relation::Thread(thread)) => Some(thread.event_id),
+                        _ => None,
+                    },
+                    body,
+                    formatted_body: formatted
+                        .filter(|f| f.format == MessageFormat::Html)
+                        .map(|f| f.body),
+                    sender: event.sender,
+                    timestamp: to_local_time(event.origin_server_ts),
+                })
+            );
+
+            if let Err(e) = room.read_receipt(&event.event_id).await {
+                log::warn!("Failed to send read receipt ({})", e);
+            }
+        }
+    }
+}
+
+async fn on_reaction(
+    event: OriginalSyncReactionEvent,
+    room: Room,
+    tx: Ctx<broadcast::Sender<Event>>,
+) {
+    if let Room::Joined(room) = room {
+        log::debug!("Received reaction in room {}", room.room_id());
+
+        crate::send_event!(
+            tx,
+            Event::MatrixReactionReceive(MatrixReactionReceiveEvent {
+                room: room.room_id().into(),
+                sender: event.sender,
+                timestamp: to_local_time(event.origin_server_ts),
+                relates_to: event.content.relates_to.event_id,
+                key: event.content.relates_to.key,
+            })
+        );
+    }
+}
+
+fn to_local_time(ts: MilliSecondsSinceUnixEpoch) -> DateTime<Local> {
+    ts.to_system_time()
+        .map(DateTime::from)
+        .unwrap_or_else(Local::now)
+}
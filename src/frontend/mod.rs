@@ -0,0 +1,172 @@
+pub(crate) mod api;
+pub(crate) mod console;
+pub(crate) mod matrix;
+pub(crate) mod webhook;
+
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use hyper::{body::HttpBody, Body, StatusCode};
+
+/// Largest request body accepted by the HTTP frontends, in bytes
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Where a command request came from, so that responses can be sent back to it.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Origin {
+    /// Name of the frontend that received the request
+    pub frontend: &'static str,
+    /// Frontend specific identifier of the conversation (e.g. a Matrix room)
+    pub channel: String,
+    /// Frontend specific identifier of the message that caused the request
+    pub message: Option<String>,
+    /// Frontend specific identifier of the thread the request was made in
+    pub thread: Option<String>,
+}
+
+/// Why a command request was refused.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Refusal {
+    /// The sender is not permitted to use the operation
+    Denied,
+    /// The command is not understood or cannot be performed
+    Invalid,
+}
+
+/// A message that has been sent by a frontend.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MessageRef {
+    pub frontend: &'static str,
+    pub channel: String,
+    pub id: String,
+}
+
+/// The outcome of sending a status notification via every frontend.
+#[derive(Debug, Default)]
+pub(crate) struct Broadcast {
+    /// Messages sent by the frontends that succeeded
+    pub ids: Vec<MessageRef>,
+    /// Frontends that failed and why
+    pub failed: Vec<(&'static str, anyhow::Error)>,
+}
+
+impl Broadcast {
+    /// Logs each frontend that failed to post `what`.
+    pub(crate) fn log_failures(&self, what: &str) {
+        for (frontend, e) in &self.failed {
+            log::warn!("Failed to post {} via {} ({:#})", what, frontend, e);
+        }
+    }
+
+    /// Fails if the named frontend could not post the notification.
+    pub(crate) fn check(&self, frontend: &str) -> Result<()> {
+        match self.failed.iter().find(|(name, _)| *name == frontend) {
+            Some((_, e)) => Err(anyhow!("{:#}", e)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A way for people to interact with the bot.
+///
+/// Frontends deliver incoming requests via the event bus (`Event::MessageReceive` unless they
+/// need special handling), this trait covers everything the processing task sends back to them.
+#[async_trait]
+pub(crate) trait Frontend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Applies a reloaded configuration.
+    fn reload(&mut self, _config: &Config) {}
+
+    /// Sends a response to a command request.
+    async fn reply(&self, origin: &Origin, body: &str) -> Result<MessageRef>;
+
+    /// Sends a response to a command request that was refused, by default as any other reply.
+    async fn refuse(&self, origin: &Origin, body: &str, _reason: Refusal) -> Result<MessageRef> {
+        self.reply(origin, body).await
+    }
+
+    /// Sends a status notification everywhere this frontend posts them.
+    async fn broadcast(&self, body: &str) -> Result<Vec<MessageRef>>;
+
+    /// Replaces the content of a message previously sent by this frontend.
+    async fn edit(&self, message: &MessageRef, body: &str) -> Result<()>;
+}
+
+/// All frontends the bot is running with.
+pub(crate) struct Frontends(Vec<Box<dyn Frontend>>);
+
+impl Frontends {
+    pub(crate) fn new(frontends: Vec<Box<dyn Frontend>>) -> Self {
+        Self(frontends)
+    }
+
+    pub(crate) fn reload(&mut self, config: &Config) {
+        for frontend in &mut self.0 {
+            frontend.reload(config);
+        }
+    }
+
+    pub(crate) async fn reply(&self, origin: &Origin, body: &str) -> Result<MessageRef> {
+        self.get(origin.frontend)?.reply(origin, body).await
+    }
+
+    pub(crate) async fn refuse(
+        &self,
+        origin: &Origin,
+        body: &str,
+        reason: Refusal,
+    ) -> Result<MessageRef> {
+        self.get(origin.frontend)?
+            .refuse(origin, body, reason)
+            .await
+    }
+
+    pub(crate) async fn edit(&self, message: &MessageRef, body: &str) -> Result<()> {
+        self.get(message.frontend)?.edit(message, body).await
+    }
+
+    /// Sends a status notification via every frontend, carrying on past any that fail.
+    pub(crate) async fn broadcast(&self, body: &str) -> Broadcast {
+        self.broadcast_except(None, body).await
+    }
+
+    /// Sends a status notification via every frontend other than `skip`.
+    pub(crate) async fn broadcast_except(&self, skip: Option<&str>, body: &str) -> Broadcast {
+        let mut broadcast = Broadcast::default();
+        for frontend in self.0.iter().filter(|f| Some(f.name()) != skip) {
+            match frontend.broadcast(body).await {
+                Ok(mut ids) => broadcast.ids.append(&mut ids),
+                Err(e) => broadcast.failed.push((frontend.name(), e)),
+            }
+        }
+        broadcast
+    }
+
+    /// Sends a status notification via the named frontend only.
+    pub(crate) async fn broadcast_via(&self, name: &str, body: &str) -> Result<Vec<MessageRef>> {
+        self.get(name)?.broadcast(body).await
+    }
+
+    fn get(&self, name: &str) -> Result<&dyn Frontend> {
+        self.0
+            .iter()
+            .find(|f| f.name() == name)
+            .map(|f| f.as_ref())
+            .ok_or_else(|| anyhow!("No frontend named {}", name))
+    }
+}
+
+/// Reads the body of a request to an HTTP frontend, failing with the status to respond with if it
+/// is too large or cannot be read.
+async fn read_body(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+        if bytes.len() + chunk.len() > MAX_REQUEST_SIZE {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,256 @@
+use super::{read_body, Frontend, MessageRef, Origin};
+use crate::{
+    config::WebhookConfig,
+    event::{Event, MessageReceiveEvent},
+    secret,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use hyper::{
+    header::AUTHORIZATION,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use matrix_sdk::ruma::OwnedUserId;
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::broadcast;
+
+pub(crate) const NAME: &str = "webhook";
+
+/// A command request posted to the webhook.
+#[derive(Debug, Deserialize, PartialEq)]
+struct CommandRequest {
+    /// Identity whose permissions apply (one of the configured senders), non-Matrix users can be
+    /// written as e.g. `@alice:irc`
+    sender: OwnedUserId,
+    text: String,
+    /// Conversation the request came from, passed back in replies
+    #[serde(default)]
+    channel: Option<String>,
+    /// Identifier of the request message, passed back in replies
+    #[serde(default)]
+    message_id: Option<String>,
+}
+
+/// A message posted to the configured URL.
+#[derive(Debug, Serialize)]
+struct OutgoingMessage<'a> {
+    id: String,
+    text: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    channel: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    in_reply_to: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replaces: Option<&'a str>,
+}
+
+/// Receives command requests as JSON over HTTP and posts messages as JSON to a URL, for
+/// integrating with chat systems other than Matrix.
+pub(crate) struct WebhookFrontend {
+    client: reqwest::Client,
+    url: String,
+    next_id: AtomicUsize,
+}
+
+impl WebhookFrontend {
+    /// Starts listening for command requests, which are sent as events to `tx`.
+    pub(crate) fn start(config: &WebhookConfig, tx: broadcast::Sender<Event>) -> Result<Self> {
+        let token = config
+            .token
+            .as_ref()
+            .map(|t| t.expose().to_string())
+            .unwrap_or_default();
+        let senders = Arc::new(config.senders.clone());
+
+        let make_service = make_service_fn(move |_| {
+            let tx = tx.clone();
+            let token = token.clone();
+            let senders = senders.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    handle_request(req, tx.clone(), token.clone(), senders.clone())
+                }))
+            }
+        });
+        let server = Server::try_bind(&config.listen_address)?.serve(make_service);
+        log::info!("Webhook listening on {}", config.listen_address);
+        tokio::spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("Webhook server failed ({})", e);
+            }
+        });
+
+        Ok(Self {
+            client: reqwest::Client::new(),
+            url: config.url.clone(),
+            next_id: AtomicUsize::new(0),
+        })
+    }
+
+    async fn post(&self, mut message: OutgoingMessage<'_>) -> Result<MessageRef> {
+        message.id = self.next_id.fetch_add(1, Ordering::Relaxed).to_string();
+
+        self.client
+            .post(&self.url)
+            .json(&message)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(MessageRef {
+            frontend: NAME,
+            channel: message.channel.unwrap_or_default().to_string(),
+            id: message.id,
+        })
+    }
+}
+
+#[async_trait]
+impl Frontend for WebhookFrontend {
+    fn name(&self) -> &'static str {
+        NAME
+    }
+
+    async fn reply(&self, origin: &Origin, body: &str) -> Result<MessageRef> {
+        self.post(OutgoingMessage {
+            id: String::default(),
+            text: body,
+            channel: Some(origin.channel.as_str()).filter(|c| !c.is_empty()),
+            in_reply_to: origin.message.as_deref(),
+            replaces: None,
+        })
+        .await
+    }
+
+    async fn broadcast(&self, body: &str) -> Result<Vec<MessageRef>> {
+        Ok(vec![
+            self.post(OutgoingMessage {
+                id: String::default(),
+                text: body,
+                channel: None,
+                in_reply_to: None,
+                replaces: None,
+            })
+            .await?,
+        ])
+    }
+
+    async fn edit(&self, message: &MessageRef, body: &str) -> Result<()> {
+        self.post(OutgoingMessage {
+            id: String::default(),
+            text: body,
+            channel: Some(message.channel.as_str()).filter(|c| !c.is_empty()),
+            in_reply_to: None,
+            replaces: Some(&message.id),
+        })
+        .await
+        .map(|_| ())
+    }
+}
+
+async fn handle_request(
+    req: Request<Body>,
+    tx: broadcast::Sender<Event>,
+    token: String,
+    senders: Arc<Vec<OwnedUserId>>,
+) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::POST {
+        return Ok(status_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+
+    let auth = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok());
+    if !authorised(auth, &token) {
+        log::warn!("Rejected webhook request with missing or incorrect token");
+        return Ok(status_response(StatusCode::UNAUTHORIZED));
+    }
+
+    let request = match read_body(req.into_body()).await {
+        Ok(body) => serde_json::from_slice::<CommandRequest>(&body),
+        Err(status) => return Ok(status_response(status)),
+    };
+    match request {
+        Ok(request) if !senders.contains(&request.sender) => {
+            log::warn!(
+                "Rejected webhook request from unlisted sender {}",
+                request.sender
+            );
+            Ok(status_response(StatusCode::FORBIDDEN))
+        }
+        Ok(request) => {
+            crate::send_event!(
+                tx,
+                Event::MessageReceive(MessageReceiveEvent {
+                    origin: Origin {
+                        frontend: NAME,
+                        channel: request.channel.unwrap_or_default(),
+                        message: request.message_id,
+                        thread: None,
+                    },
+                    sender: request.sender,
+                    body: request.text,
+                    addressed: false,
+                })
+            );
+            Ok(status_response(StatusCode::ACCEPTED))
+        }
+        Err(e) => {
+            log::warn!("Failed to parse webhook request ({})", e);
+            Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(e.to_string()))
+                .unwrap())
+        }
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+fn authorised(header: Option<&str>, token: &str) -> bool {
+    header
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .is_some_and(|presented| secret::token_matches(token, presented))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn authorisation() {
+        assert!(authorised(Some("Bearer hunter2"), "hunter2"));
+        assert!(!authorised(Some("Bearer hunter3"), "hunter2"));
+        assert!(!authorised(Some("hunter2"), "hunter2"));
+        assert!(!authorised(None, "hunter2"));
+        assert!(!authorised(Some("Bearer "), ""));
+    }
+
+    #[test]
+    fn parse_request() {
+        let request: CommandRequest =
+            serde_json::from_str(r#"{"sender": "@alice:irc", "text": "!mb7pmf power off"}"#)
+                .unwrap();
+        assert_eq!(request.sender.as_str(), "@alice:irc");
+        assert_eq!(request.channel, None);
+
+        assert!(serde_json::from_str::<CommandRequest>(
+            r#"{"sender": "alice", "text": "!mb7pmf power off"}"#
+        )
+        .is_err());
+    }
+}
@@ -1,26 +1,45 @@
+mod action;
+mod broker;
+mod channel;
 mod command;
+mod config;
+mod connectivity;
+mod encoding;
 mod event;
+mod fields;
+mod frontend;
 mod metrics;
 mod processing;
+mod relay;
 mod schema;
+mod secret;
+mod send;
+mod signing;
+mod simulate;
+mod status_buffer;
+mod template;
+mod transport;
 
-use crate::event::{Event, MatrixMessageReceiveEvent};
-use anyhow::Result;
-use clap::Parser;
-use kagiyama::{AlwaysReady, Watcher};
-use matrix_sdk::{
-    event_handler::Ctx,
-    room::Room,
-    ruma::{
-        events::room::message::{
-            MessageType, OriginalSyncRoomMessageEvent, TextMessageEventContent,
-        },
-        OwnedRoomId, OwnedUserId,
+use crate::{
+    command::ReactionCommand,
+    config::{Config, LinkConfig, TransportKind},
+    event::Event,
+    frontend::{
+        api::ApiFrontend, console::ConsoleFrontend, matrix::MatrixFrontend,
+        webhook::WebhookFrontend, Frontend, Frontends,
     },
+    secret::Secret,
+    status_buffer::StatusBuffer,
+};
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand};
+use kagiyama::{AlwaysReady, Watcher};
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+use std::{future::Future, net::SocketAddr, path::PathBuf, time::Duration};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::broadcast,
 };
-use mqtt_channel_client as mqtt;
-use std::{net::SocketAddr, path::PathBuf, time::Duration};
-use tokio::sync::broadcast;
 
 #[macro_export]
 macro_rules! send_event {
@@ -33,74 +52,348 @@ macro_rules! send_event {
 
 /// A Matrix bot that provides a nice interface to remote-closedown.
 #[derive(Clone, Debug, Parser)]
-#[clap(author, version, about, long_about = None)]
+#[clap(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Cli {
-    /// Address of MQTT broker to connect to
-    #[clap(
-        value_parser,
-        long,
-        env = "MQTT_BROKER",
-        default_value = "tcp://localhost:1883"
-    )]
-    mqtt_broker: String,
+    #[clap(subcommand)]
+    command: Option<CliCommand>,
+
+    #[clap(flatten)]
+    config: ConfigArgs,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+enum CliCommand {
+    /// Run the bot (the default when no subcommand is given)
+    Run(ConfigArgs),
+
+    /// Validate the configuration without connecting to anything
+    CheckConfig(ConfigArgs),
+
+    /// Send a command directly to the station over MQTT and wait for it to take effect
+    Send(SendArgs),
+
+    /// Pretend to be a station, responding to commands over MQTT
+    Simulate(SimulateArgs),
+
+    /// Operate the station from the terminal, without connecting to Matrix
+    Console(ConsoleArgs),
+
+    /// Print a JSON Schema for the messages exchanged with the station
+    Schema(SchemaArgs),
+}
+
+#[derive(Clone, Debug, Args)]
+struct SendArgs {
+    #[clap(flatten)]
+    config: ConfigArgs,
+
+    /// Time (in seconds) to wait for the station to reach the expected state
+    #[clap(value_parser, long, default_value = "10")]
+    timeout: u64,
+
+    /// Command to send (e.g. "power off")
+    #[clap(value_parser, required = true)]
+    command: Vec<String>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct SimulateArgs {
+    #[clap(flatten)]
+    config: ConfigArgs,
+
+    /// Fault to inject into the simulated station (may be given multiple times)
+    #[clap(value_enum, long = "fault")]
+    faults: Vec<simulate::Fault>,
+}
+
+#[derive(Clone, Debug, Args)]
+struct ConsoleArgs {
+    #[clap(flatten)]
+    config: ConfigArgs,
+
+    /// Matrix user whose permissions apply to commands typed on the console
+    #[clap(value_parser, long)]
+    user: OwnedUserId,
+}
+
+#[derive(Clone, Debug, Args)]
+struct SchemaArgs {
+    #[clap(flatten)]
+    config: ConfigArgs,
+
+    /// Message format version [default: the station's schema_version]
+    #[clap(value_parser, long)]
+    version: Option<u32>,
+
+    /// Message to describe
+    #[clap(value_enum)]
+    message: schema::Message,
+}
+
+#[derive(Clone, Debug, Args)]
+struct ConfigArgs {
+    /// Configuration file, options given on the command line take precedence over it
+    #[clap(value_parser, long, env = "CONFIG_FILE")]
+    config: Option<PathBuf>,
+
+    /// Addresses of MQTT brokers to connect to, in order of preference [default: tcp://localhost:1883]
+    #[clap(value_parser, long, env = "MQTT_BROKER", value_delimiter = ',')]
+    mqtt_broker: Vec<String>,
+
+    /// Client ID to use when connecting to MQTT broker [default: matrix-remote-closedown]
+    #[clap(value_parser, long, env = "MQTT_CLIENT_ID")]
+    mqtt_client_id: Option<String>,
+
+    /// MQTT QoS, must be 0, 1 or 2 [default: 0]
+    #[clap(value_parser, long, env = "MQTT_QOS")]
+    mqtt_qos: Option<i32>,
+
+    /// Topic to publish the bot's availability (online/offline) to
+    #[clap(value_parser, long, env = "MQTT_AVAILABILITY_TOPIC")]
+    mqtt_availability_topic: Option<String>,
+
+    /// MQTT protocol version, 3 (3.1.1) or 5 [default: 3]
+    #[clap(value_parser, long, env = "MQTT_VERSION")]
+    mqtt_version: Option<u32>,
 
-    /// Client ID to use when connecting to MQTT broker
+    /// MQTT username
+    #[clap(value_parser, long, env = "MQTT_USERNAME")]
+    mqtt_username: Option<String>,
+
+    /// MQTT password
+    #[clap(value_parser, long, env = "MQTT_PASSWORD")]
+    mqtt_password: Option<Secret>,
+
+    /// File to read the MQTT password from
     #[clap(
         value_parser,
         long,
-        env = "MQTT_CLIENT_ID",
-        default_value = "matrix-remote-closedown"
+        env = "MQTT_PASSWORD_FILE",
+        conflicts_with = "mqtt_password"
     )]
-    mqtt_client_id: String,
+    mqtt_password_file: Option<PathBuf>,
 
-    /// MQTT QoS, must be 0, 1 or 2
-    #[clap(value_parser, long, env = "MQTT_QOS", default_value = "0")]
-    mqtt_qos: i32,
+    /// CA certificates (PEM) to verify the MQTT broker with, instead of the system defaults
+    #[clap(value_parser, long, env = "MQTT_CA_FILE")]
+    mqtt_ca_file: Option<PathBuf>,
 
-    /// MQTT username
-    #[clap(value_parser, long, env = "MQTT_USERNAME", default_value = "")]
-    mqtt_username: String,
+    /// Client certificate (PEM) to present to the MQTT broker
+    #[clap(value_parser, long, env = "MQTT_CERT_FILE", requires = "mqtt_key_file")]
+    mqtt_cert_file: Option<PathBuf>,
 
-    /// MQTT password
-    #[clap(value_parser, long, env = "MQTT_PASSWORD", default_value = "")]
-    mqtt_password: String,
+    /// Private key (PEM) for the MQTT client certificate
+    #[clap(value_parser, long, env = "MQTT_KEY_FILE", requires = "mqtt_cert_file")]
+    mqtt_key_file: Option<PathBuf>,
+
+    /// Do not check that the MQTT broker's certificate matches its hostname
+    #[clap(long, env = "MQTT_NO_VERIFY_HOSTNAME")]
+    mqtt_no_verify_hostname: bool,
 
     /// Matrix username
     #[clap(value_parser, long, env = "MATRIX_USERNAME")]
-    matrix_username: OwnedUserId,
+    matrix_username: Option<OwnedUserId>,
 
     /// Matrix password
     #[clap(value_parser, long, env = "MATRIX_PASSWORD")]
-    matrix_password: String,
+    matrix_password: Option<Secret>,
+
+    /// File to read the Matrix password from
+    #[clap(
+        value_parser,
+        long,
+        env = "MATRIX_PASSWORD_FILE",
+        conflicts_with = "matrix_password"
+    )]
+    matrix_password_file: Option<PathBuf>,
 
     /// Matrix storage directory
     #[clap(value_parser, long, env = "MATRIX_STORAGE")]
-    matrix_storage: PathBuf,
+    matrix_storage: Option<PathBuf>,
 
     /// Topic to listen for status messages on
     #[clap(value_parser, long, env = "STATUS_TOPIC")]
-    status_topic: String,
+    status_topic: Option<String>,
 
     /// Topic to send command messages on
     #[clap(value_parser, long, env = "COMMAND_TOPIC")]
-    command_topic: String,
+    command_topic: Option<String>,
+
+    /// Topic the station replies to commands on (MQTT v5 only)
+    #[clap(value_parser, long, env = "RESPONSE_TOPIC")]
+    response_topic: Option<String>,
 
     /// Station name
     #[clap(value_parser, long, env = "STATION_NAME")]
-    station_name: String,
+    station_name: Option<String>,
 
-    /// Matrix rooms to send messages to and listen for commands from
-    #[clap(value_parser, long = "room")]
-    matrix_rooms: Vec<OwnedRoomId>,
+    /// How to communicate with the station [default: mqtt]
+    #[clap(value_enum, long, env = "TRANSPORT")]
+    transport: Option<TransportKind>,
+
+    /// How messages exchanged with the station are serialised [default: json]
+    #[clap(value_enum, long, env = "STATION_ENCODING")]
+    encoding: Option<encoding::Encoding>,
+
+    /// Serial port the station is connected to, when using the serial transport
+    #[clap(value_parser, long, env = "SERIAL_PORT")]
+    serial_port: Option<String>,
 
-    /// Address to listen on for observability/metrics endpoints
+    /// Serial port baud rate [default: 9600]
+    #[clap(value_parser, long, env = "SERIAL_BAUD_RATE")]
+    serial_baud_rate: Option<u32>,
+
+    /// Key shared with the station for signing commands and status messages
+    #[clap(value_parser, long, env = "SIGNING_KEY")]
+    signing_key: Option<Secret>,
+
+    /// File to read the signing key from
     #[clap(
         value_parser,
         long,
-        env = "OBSERVABILITY_ADDRESS",
-        default_value = "127.0.0.1:9090"
+        env = "SIGNING_KEY_FILE",
+        conflicts_with = "signing_key"
     )]
-    observability_address: SocketAddr,
+    signing_key_file: Option<PathBuf>,
+
+    /// Matrix rooms to send messages to and listen for commands from
+    #[clap(value_parser, long = "room")]
+    matrix_rooms: Vec<OwnedRoomId>,
+
+    /// Operation to perform when a status message is reacted to with an emoji, in the form
+    /// EMOJI=COMMAND (e.g. "🛑=shutdown")
+    #[clap(value_parser, long = "reaction")]
+    reaction_commands: Vec<ReactionCommand>,
+
+    /// Maximum age (in seconds) of a command message for it to be acted upon [default: 60]
+    #[clap(value_parser, long, env = "COMMAND_MAX_AGE")]
+    command_max_age: Option<u64>,
+
+    /// Maximum number of status updates to retain while Matrix is unreachable, at least 1 [default: 100]
+    #[clap(value_parser, long, env = "STATUS_BUFFER_SIZE")]
+    status_buffer_size: Option<usize>,
+
+    /// Address to listen on for observability/metrics endpoints [default: 127.0.0.1:9090]
+    #[clap(value_parser, long, env = "OBSERVABILITY_ADDRESS")]
+    observability_address: Option<SocketAddr>,
+}
+
+impl ConfigArgs {
+    fn load_config(&self) -> Result<Config> {
+        Config::load(self.config.as_deref(), self.overrides())
+    }
+
+    fn load_console_config(&self) -> Result<Config> {
+        Config::load_console(self.config.as_deref(), self.overrides())
+    }
+
+    fn load_link_config(&self) -> Result<LinkConfig> {
+        LinkConfig::load(self.config.as_deref(), self.overrides())
+    }
+
+    fn overrides(&self) -> Vec<(&str, toml::Value)> {
+        let mut overrides: Vec<(&str, toml::Value)> = Vec::new();
+
+        macro_rules! set {
+            ($key:expr, $value:expr) => {
+                if let Some(v) = &$value {
+                    overrides.push(($key, v.to_string().into()));
+                }
+            };
+        }
+
+        if !self.mqtt_broker.is_empty() {
+            overrides.push(("mqtt.broker", self.mqtt_broker.clone().into()));
+        }
+        set!("mqtt.client_id", self.mqtt_client_id);
+        if let Some(qos) = self.mqtt_qos {
+            overrides.push(("mqtt.qos", qos.into()));
+        }
+        set!("mqtt.availability_topic", self.mqtt_availability_topic);
+        if let Some(version) = self.mqtt_version {
+            overrides.push(("mqtt.version", toml::Value::Integer(version.into())));
+        }
+        set!("mqtt.username", self.mqtt_username);
+        set!(
+            "mqtt.password",
+            self.mqtt_password.as_ref().map(Secret::expose)
+        );
+        set!(
+            "mqtt.password_file",
+            self.mqtt_password_file.as_ref().map(|p| p.display())
+        );
+        set!(
+            "mqtt.tls.ca_file",
+            self.mqtt_ca_file.as_ref().map(|p| p.display())
+        );
+        set!(
+            "mqtt.tls.cert_file",
+            self.mqtt_cert_file.as_ref().map(|p| p.display())
+        );
+        set!(
+            "mqtt.tls.key_file",
+            self.mqtt_key_file.as_ref().map(|p| p.display())
+        );
+        if self.mqtt_no_verify_hostname {
+            overrides.push(("mqtt.tls.verify_hostname", false.into()));
+        }
+        set!("matrix.username", self.matrix_username);
+        set!(
+            "matrix.password",
+            self.matrix_password.as_ref().map(Secret::expose)
+        );
+        set!(
+            "matrix.password_file",
+            self.matrix_password_file.as_ref().map(|p| p.display())
+        );
+        set!(
+            "matrix.storage",
+            self.matrix_storage.as_ref().map(|p| p.display())
+        );
+        set!("station.name", self.station_name);
+        set!("station.status_topic", self.status_topic);
+        set!("station.command_topic", self.command_topic);
+        set!("station.response_topic", self.response_topic);
+        set!("station.transport", self.transport);
+        set!("station.encoding", self.encoding);
+        set!("serial.port", self.serial_port);
+        if let Some(baud_rate) = self.serial_baud_rate {
+            overrides.push(("serial.baud_rate", toml::Value::Integer(baud_rate.into())));
+        }
+        set!("signing.key", self.signing_key.as_ref().map(Secret::expose));
+        set!(
+            "signing.key_file",
+            self.signing_key_file.as_ref().map(|p| p.display())
+        );
+        if !self.matrix_rooms.is_empty() {
+            overrides.push((
+                "rooms",
+                self.matrix_rooms
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+                    .into(),
+            ));
+        }
+        if !self.reaction_commands.is_empty() {
+            overrides.push((
+                "commands.reactions",
+                self.reaction_commands
+                    .iter()
+                    .map(|r| r.to_string())
+                    .collect::<Vec<_>>()
+                    .into(),
+            ));
+        }
+        if let Some(max_age) = self.command_max_age {
+            overrides.push(("commands.max_age", toml::Value::Integer(max_age as i64)));
+        }
+        if let Some(size) = self.status_buffer_size {
+            overrides.push(("status_buffer_size", toml::Value::Integer(size as i64)));
+        }
+        set!("observability_address", self.observability_address);
+
+        overrides
+    }
 }
 
 #[tokio::main]
@@ -109,94 +402,247 @@ async fn main() -> Result<()> {
 
     let args = Cli::parse();
 
-    let mqtt_client = mqtt::Client::new(
-        mqtt::paho_mqtt::create_options::CreateOptionsBuilder::new()
-            .server_uri(&args.mqtt_broker)
-            .client_id(&args.mqtt_client_id)
-            .persistence(mqtt::paho_mqtt::PersistenceType::None)
-            .finalize(),
-        mqtt::ClientConfig::default(),
-    )?;
+    match args.command {
+        None => run(args.config).await,
+        Some(CliCommand::Run(args)) => run(args).await,
+        Some(CliCommand::CheckConfig(args)) => check_config(args),
+        Some(CliCommand::Send(args)) => {
+            send::run(
+                args.config.load_link_config()?,
+                &args.command.join(" "),
+                Duration::from_secs(args.timeout),
+            )
+            .await
+        }
+        Some(CliCommand::Simulate(args)) => {
+            simulate::run(args.config.load_link_config()?, args.faults).await
+        }
+        Some(CliCommand::Console(args)) => console(args).await,
+        Some(CliCommand::Schema(args)) => print_schema(args),
+    }
+}
 
-    let mut watcher = Watcher::<AlwaysReady>::default();
-    {
-        let mut registry = watcher.metrics_registry();
-        let registry = registry.sub_registry_with_prefix("matrixremoteclosedown");
-        mqtt_client.register_metrics(registry);
-        registry.register("commands", "Command requests", metrics::COMMANDS.clone());
+fn print_schema(args: SchemaArgs) -> Result<()> {
+    let config = args.config.load_link_config()?;
+    let version = args.version.unwrap_or(config.station.schema_version);
+    if !schema::SUPPORTED_VERSIONS.contains(&version) {
+        return Err(anyhow!(
+            "Unsupported schema version {}, supported versions are {:?}",
+            version,
+            schema::SUPPORTED_VERSIONS
+        ));
     }
-    watcher.start_server(args.observability_address).await;
 
-    let (tx, _) = broadcast::channel::<Event>(16);
+    let schema = schema::json_schema(args.message, version, &config.channels);
+    println!("{}", serde_json::to_string_pretty(&schema)?);
+    Ok(())
+}
 
-    mqtt_client.subscribe(
-        mqtt::SubscriptionBuilder::default()
-            .topic(args.status_topic.clone())
-            .build()
-            .unwrap(),
+fn check_config(args: ConfigArgs) -> Result<()> {
+    let config = match Config::load_unchecked(args.config.as_deref(), args.overrides()) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("error: {:#}", e);
+            return Err(anyhow!("Configuration could not be loaded"));
+        }
+    };
+
+    let issues = config.check();
+    for issue in &issues {
+        println!("{}", issue);
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|i| matches!(i, config::Issue::Error(_)))
+        .count();
+    println!(
+        "Station {}: {} error(s), {} warning(s)",
+        config.station.name,
+        errors,
+        issues.len() - errors
     );
-    mqtt_client
-        .start(
-            mqtt::paho_mqtt::connect_options::ConnectOptionsBuilder::new()
-                .clean_session(true)
-                .automatic_reconnect(Duration::from_secs(1), Duration::from_secs(5))
-                .keep_alive_interval(Duration::from_secs(5))
-                .user_name(&args.mqtt_username)
-                .password(&args.mqtt_password)
-                .finalize(),
-        )
-        .await?;
-
-    let matrix_client = matrix_client_boilerplate::Client::new(
-        args.matrix_username.as_str(),
-        &args.matrix_password,
-        "matrix-remote-closedown",
-        &args.matrix_storage,
+
+    if errors == 0 {
+        Ok(())
+    } else {
+        Err(anyhow!("Configuration is invalid"))
+    }
+}
+
+async fn run(args: ConfigArgs) -> Result<()> {
+    let config = args.load_config()?;
+    let (tx, _) = broadcast::channel::<Event>(16);
+
+    let mut frontends: Vec<Box<dyn Frontend>> =
+        vec![Box::new(MatrixFrontend::login(&config, tx.clone()).await?)];
+    if let Some(webhook) = &config.webhook {
+        frontends.push(Box::new(WebhookFrontend::start(webhook, tx.clone())?));
+    }
+    if let Some(api) = &config.api {
+        frontends.push(Box::new(ApiFrontend::start(
+            api,
+            &config.station,
+            tx.clone(),
+        )?));
+    }
+
+    let client_id = config.mqtt.client_id.clone();
+    serve(
+        &args,
+        config,
+        &client_id,
+        tx,
+        Frontends::new(frontends),
+        Mode::Bot,
+        std::future::pending::<()>(),
     )
-    .await?;
-    matrix_client.initial_sync().await?;
+    .await
+}
 
-    matrix_client.client().add_event_handler_context(tx.clone());
-    matrix_client.client().add_event_handler(on_room_message);
+async fn console(args: ConsoleArgs) -> Result<()> {
+    let config = args.config.load_console_config()?;
+    let (tx, _) = broadcast::channel::<Event>(16);
 
-    matrix_client.start_background_sync().await;
+    let (console, input) = ConsoleFrontend::start(tx.clone(), args.user);
+    println!(
+        "Operating {}, type `help` for usage or press Ctrl+D to exit",
+        config.station.name
+    );
 
-    let processing_task = processing::run_task(
-        tx.clone(),
-        mqtt_client,
-        matrix_client.client().clone(),
-        args.clone(),
-    )?;
+    // A separate client ID so as not to disconnect a bot that is already running
+    let client_id = format!("{}-console", config.mqtt.client_id);
+    serve(
+        &args.config,
+        config,
+        &client_id,
+        tx,
+        Frontends::new(vec![Box::new(console)]),
+        Mode::Console,
+        input,
+    )
+    .await
+}
 
-    tokio::signal::ctrl_c().await.unwrap();
-    log::info! {"Terminating"};
-    tx.send(Event::Exit)?;
-    let _ = processing_task.await;
+/// Whether [`serve`] is running the bot or an interactive session alongside it.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Bot,
+    /// Leaves Matrix, availability, the status buffer and observability address to the bot
+    Console,
+}
 
-    Ok(())
+impl Mode {
+    fn load_config(self, args: &ConfigArgs) -> Result<Config> {
+        match self {
+            Self::Bot => args.load_config(),
+            Self::Console => args.load_console_config(),
+        }
+    }
 }
 
-async fn on_room_message(
-    event: OriginalSyncRoomMessageEvent,
-    room: Room,
-    tx: Ctx<broadcast::Sender<Event>>,
-) {
-    if let Room::Joined(room) = room {
-        if let MessageType::Text(TextMessageEventContent { body, .. }) = event.content.msgtype {
-            log::debug!("Received message in room {}", room.room_id());
-
-            crate::send_event!(
-                tx,
-                Event::MatrixMessageReceive(MatrixMessageReceiveEvent {
-                    room: room.room_id().into(),
-                    body,
-                    sender: event.sender,
-                })
-            );
-
-            if let Err(e) = room.read_receipt(&event.event_id).await {
-                log::warn!("Failed to send read receipt ({})", e);
+/// Connects to MQTT and processes events until interrupted or `until` completes.
+async fn serve(
+    args: &ConfigArgs,
+    config: Config,
+    client_id: &str,
+    tx: broadcast::Sender<Event>,
+    frontends: Frontends,
+    mode: Mode,
+    until: impl Future,
+) -> Result<()> {
+    let transport = transport::connect(&config.link(), client_id, tx.clone()).await?;
+
+    let mut watcher = Watcher::<AlwaysReady>::default();
+    {
+        let mut registry = watcher.metrics_registry();
+        let registry = registry.sub_registry_with_prefix("matrixremoteclosedown");
+        transport.register_metrics(registry);
+        registry.register("commands", "Command requests", metrics::COMMANDS.clone());
+        registry.register(
+            "command_outcomes",
+            "Commands sent to the station, by whether they took effect",
+            metrics::COMMAND_OUTCOMES.clone(),
+        );
+        registry.register(
+            "stale_commands",
+            "Command requests ignored for being older than the freshness window",
+            metrics::STALE_COMMANDS.clone(),
+        );
+        registry.register(
+            "denied_commands",
+            "Command requests refused because the sender lacks permission",
+            metrics::DENIED_COMMANDS.clone(),
+        );
+        registry.register(
+            "active_broker",
+            "MQTT broker currently in use (1 for the active broker)",
+            metrics::ACTIVE_BROKER.clone(),
+        );
+        registry.register(
+            "broker_switches",
+            "Switches between MQTT brokers",
+            metrics::BROKER_SWITCHES.clone(),
+        );
+        registry.register(
+            "rejected_messages",
+            "Status messages discarded for a missing or invalid signature",
+            metrics::REJECTED_MESSAGES.clone(),
+        );
+        registry.register(
+            "relayed_messages",
+            "Messages relayed from other MQTT topics",
+            metrics::RELAYED_MESSAGES.clone(),
+        );
+        registry.register(
+            "channel_state",
+            "Last reported state of each station channel",
+            metrics::CHANNEL_STATE.clone(),
+        );
+        registry.register(
+            "status_field",
+            "Last reported value of each configured numeric status field",
+            metrics::STATUS_FIELDS.clone(),
+        );
+        registry.register(
+            "field_alerts",
+            "Status fields found outside of their configured range",
+            metrics::FIELD_ALERTS.clone(),
+        );
+    }
+    let status_buffer = match mode {
+        Mode::Bot => {
+            watcher.start_server(config.observability_address).await;
+            StatusBuffer::load(
+                config.matrix.storage.join("status_buffer.json"),
+                config.status_buffer_size,
+            )
+        }
+        Mode::Console => StatusBuffer::in_memory(config.status_buffer_size),
+    };
+
+    let processing_task =
+        processing::run_task(tx.clone(), transport, frontends, config, status_buffer)?;
+
+    let mut sighup = signal(SignalKind::hangup())?;
+    tokio::pin!(until);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = &mut until => break,
+            _ = sighup.recv() => {
+                log::info!("Reloading configuration");
+                match mode.load_config(args) {
+                    Ok(config) => crate::send_event!(tx, Event::ConfigReload(Box::new(config))),
+                    Err(e) => log::error!("Failed to reload configuration, keeping current configuration ({:#})", e),
+                }
             }
         }
     }
+
+    log::info! {"Terminating"};
+    tx.send(Event::Exit)?;
+    let _ = processing_task.await;
+
+    Ok(())
 }
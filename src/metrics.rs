@@ -1,10 +1,11 @@
 use crate::command::Operation;
 use kagiyama::prometheus::{
     self as prometheus_client,
-    encoding::EncodeLabelSet,
-    metrics::{counter::Counter, family::Family},
+    encoding::{EncodeLabelSet, EncodeLabelValue},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
 };
 use lazy_static::lazy_static;
+use std::sync::atomic::AtomicU64;
 
 #[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
 pub(crate) struct CommandLables {
@@ -17,7 +18,92 @@ impl CommandLables {
     }
 }
 
+/// What became of a command sent to the station.
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelValue)]
+pub(crate) enum Outcome {
+    /// The station reported the requested state
+    Confirmed,
+    /// The station replied, but did not report the requested state
+    NotApplied,
+    /// The station did not reply in time
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct OutcomeLabels {
+    operation: Operation,
+    outcome: Outcome,
+}
+
+impl OutcomeLabels {
+    pub(crate) fn new(operation: Operation, outcome: Outcome) -> Self {
+        Self { operation, outcome }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct BrokerLabels {
+    broker: String,
+}
+
+impl BrokerLabels {
+    pub(crate) fn new(broker: &str) -> Self {
+        Self {
+            broker: broker.to_string(),
+        }
+    }
+}
+
+/// Which of a channel's status fields a gauge reports.
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelValue)]
+pub(crate) enum ChannelField {
+    Enabled,
+    Active,
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct ChannelLabels {
+    channel: String,
+    field: ChannelField,
+}
+
+impl ChannelLabels {
+    pub(crate) fn new(channel: &str, field: ChannelField) -> Self {
+        Self {
+            channel: channel.to_string(),
+            field,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, Hash, PartialEq, EncodeLabelSet)]
+pub(crate) struct StatusFieldLabels {
+    field: String,
+}
+
+impl StatusFieldLabels {
+    pub(crate) fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+        }
+    }
+}
+
 lazy_static! {
     pub(crate) static ref COMMANDS: Family::<CommandLables, Counter> =
         Family::<CommandLables, Counter>::default();
+    pub(crate) static ref COMMAND_OUTCOMES: Family::<OutcomeLabels, Counter> =
+        Family::<OutcomeLabels, Counter>::default();
+    pub(crate) static ref STALE_COMMANDS: Counter = Counter::default();
+    pub(crate) static ref DENIED_COMMANDS: Counter = Counter::default();
+    pub(crate) static ref ACTIVE_BROKER: Family::<BrokerLabels, Gauge> =
+        Family::<BrokerLabels, Gauge>::default();
+    pub(crate) static ref BROKER_SWITCHES: Counter = Counter::default();
+    pub(crate) static ref REJECTED_MESSAGES: Counter = Counter::default();
+    pub(crate) static ref RELAYED_MESSAGES: Counter = Counter::default();
+    pub(crate) static ref CHANNEL_STATE: Family::<ChannelLabels, Gauge> =
+        Family::<ChannelLabels, Gauge>::default();
+    pub(crate) static ref STATUS_FIELDS: Family::<StatusFieldLabels, Gauge<f64, AtomicU64>> =
+        Family::<StatusFieldLabels, Gauge<f64, AtomicU64>>::default();
+    pub(crate) static ref FIELD_ALERTS: Counter = Counter::default();
 }
@@ -1,38 +1,73 @@
 use crate::{
-    command::Operation,
-    event::{CommandEvent, Event},
-    metrics::{CommandLables, COMMANDS},
+    action::{self, Action, Step},
+    channel,
+    command::{extract_command_text, Command, Operation},
+    config::{Config, StationConfig, Templates},
+    connectivity::Notices,
+    encoding,
+    event::{CommandEvent, CommandMessage, CommandReplyEvent, Event, MacroStepEvent},
+    fields::{self, Alerts},
+    frontend::{
+        matrix::{self, ResponseTarget},
+        Frontends, MessageRef, Origin, Refusal,
+    },
+    metrics::{
+        ChannelField, ChannelLabels, CommandLables, Outcome, OutcomeLabels, StatusFieldLabels,
+        CHANNEL_STATE, COMMANDS, COMMAND_OUTCOMES, DENIED_COMMANDS, FIELD_ALERTS, RELAYED_MESSAGES,
+        STALE_COMMANDS, STATUS_FIELDS,
+    },
+    relay::{RelayedMessage, Relays},
     schema::{self, Response, Status},
-    Cli,
+    status_buffer::{BufferedStatus, StatusBuffer},
+    transport::{new_correlation_id, Transport},
+};
+use anyhow::{anyhow, Result};
+use chrono::{offset::Local, DateTime};
+use matrix_sdk::ruma::{OwnedRoomId, OwnedUserId};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
 };
-use anyhow::Result;
-use matrix_sdk::ruma::events::room::message::RoomMessageEventContent;
-use mqtt_channel_client as mqtt;
 use tokio::{sync::broadcast::Sender, task::JoinHandle};
 use unindent::Unindent;
 
-macro_rules! format_optional_bool {
-    ($v:expr, $str_true:expr, $str_false:expr, $str_none:expr) => {
-        match $v {
-            Some(true) => $str_true,
-            Some(false) => $str_false,
-            None => $str_none,
-        }
-    };
+/// Number of recent status messages that can be reacted to
+const STATUS_MESSAGE_HISTORY: usize = 32;
+
+/// Number of commands that can be awaiting confirmation from the station
+const PENDING_COMMAND_HISTORY: usize = 32;
+
+/// A command that has been sent to the station, but not yet seen to take effect.
+struct PendingCommand {
+    op: Operation,
+    cmd: schema::Command,
+    /// Acknowledgement message, edited once the command takes effect
+    message: MessageRef,
+    correlation_id: String,
+    sent: Instant,
+    /// The station will reply to this command specifically, rather than only updating its status
+    awaiting_reply: bool,
 }
 
 pub(crate) fn run_task(
     tx: Sender<Event>,
-    mqtt_client: mqtt_channel_client::Client,
-    matrix_client: matrix_sdk::Client,
-    config: Cli,
+    transport: Box<dyn Transport>,
+    mut frontends: Frontends,
+    mut config: Config,
+    mut status_buffer: StatusBuffer,
 ) -> Result<JoinHandle<()>> {
     let mut rx = tx.subscribe();
 
     Ok(tokio::spawn(async move {
-        let mut mqtt_rx = mqtt_client.rx_channel();
-
         let mut old_status = Status::default();
+        let mut status_message_ids = VecDeque::<MessageRef>::new();
+        let mut pending_commands = VecDeque::<PendingCommand>::new();
+        let mut connectivity_notices = Notices::default();
+        let mut relays = Relays::default();
+        let mut field_alerts = Alerts::default();
+
+        let mut status_buffer_retry = tokio::time::interval(Duration::from_secs(30));
+        let mut reply_timeout_check = tokio::time::interval(Duration::from_secs(5));
 
         loop {
             tokio::select! {
@@ -40,195 +75,228 @@ pub(crate) fn run_task(
                     match event {
                         Event::Exit => {
                             log::debug!("Task exit");
+                            transport.shutdown();
                             return;
                         }
-                        Event::MqttSendCommandMessage(msg) => {
-                            log::info!("Sending command message: {}", msg);
-                            if let Err(e) = mqtt_client.send(mqtt::paho_mqtt::Message::new(&config.command_topic, msg, 2)) {
+                        Event::ConfigReload(new_config) => {
+                            config.reload(*new_config);
+                            frontends.reload(&config);
+                            log::info!("Configuration reloaded");
+                        }
+                        Event::SendCommandMessage(msg) => {
+                            log::info!("Sending command message: {} ({})", encoding::describe(&msg.payload), msg.correlation_id);
+                            let result = match &msg.topic {
+                                Some(topic) => transport.publish(topic, msg.payload),
+                                None => transport.send_command(msg.payload, &msg.correlation_id),
+                            };
+                            if let Err(e) = result {
                                 log::warn!("Error sending command message ({})", e);
                             }
                         },
-                        Event::MatrixMessageReceive(event) => {
-                            if !event.body.starts_with('!') {
-                                log::debug!("Ignoring message with no command marker");
+                        Event::BrokerChanged(event) => {
+                            let body = if event.returned {
+                                format!(
+                                    "**{}**: preferred MQTT broker {} is reachable again, switched back from {}",
+                                    config.station.name, event.to, event.from
+                                )
+                            } else {
+                                format!(
+                                    "**{}**: MQTT broker {} is unreachable, switched to {}",
+                                    config.station.name, event.from, event.to
+                                )
+                            };
+                            frontends.broadcast(&body).await.log_failures("broker change");
+                        }
+                        Event::ConnectivityChanged(event) => {
+                            if let Some(notice) = connectivity_notices.update(
+                                &config.connectivity,
+                                event.service,
+                                event.connected,
+                                event.timestamp,
+                            ) {
+                                let body = format!("**{}**: {}", config.station.name, notice);
+                                // Loss of Matrix itself can only reach the other frontends
+                                frontends.broadcast(&body).await.log_failures("connectivity change");
+                            }
+                        }
+                        Event::RelayMessageReceived(event) => {
+                            for message in relays.handle(
+                                &config.station.name,
+                                &config.relays,
+                                &event.topic,
+                                &event.payload,
+                                Local::now(),
+                            ) {
+                                post_relayed_message(&frontends, message).await;
+                            }
+                        }
+                        Event::MatrixMessageReceive(mut event) => {
+                            event.body = match extract_command_text(
+                                &event.body,
+                                event.formatted_body.as_deref(),
+                                &config.matrix.username,
+                                &config.station.name,
+                            ) {
+                                Some(body) => body,
+                                None => {
+                                    log::debug!("Ignoring message with no command marker");
+                                    continue;
+                                }
+                            };
+
+                            if !accept_command_source(&config, &event.room, &event.sender, event.timestamp, &event.body) {
                                 continue;
                             }
 
-                            let room = event.room.clone();
-                            if !config.matrix_rooms.contains(&room) {
-                                log::debug!("Ignoring message in room we do not watch");
+                            log::info!("Message from Matrix: {}", event.body);
+                            let origin = ResponseTarget {
+                                event_id: event.event_id,
+                                thread_root: event.thread_root,
+                            }
+                            .origin(&event.room);
+                            request_command(&tx, &frontends, &config, origin, event.sender, event.body).await;
+                        }
+                        Event::MessageReceive(event) => {
+                            let body = event.body.trim();
+                            let body = if body.starts_with('!') {
+                                body.to_string()
+                            } else if event.addressed {
+                                format!("!{} {}", config.station.name, body)
+                            } else {
+                                log::debug!("Ignoring message with no command marker");
+                                continue;
+                            };
+
+                            log::info!("Message from {}: {}", event.origin.frontend, body);
+                            request_command(&tx, &frontends, &config, event.origin, event.sender, body).await;
+                        }
+                        Event::MatrixReactionReceive(event) => {
+                            if !status_message_ids
+                                .iter()
+                                .any(|m| m.frontend == matrix::NAME && m.id == event.relates_to.as_str())
+                            {
+                                log::debug!("Ignoring reaction to a message that is not a status message");
                                 continue;
                             }
 
-                            let sender = event.sender.clone();
-                            if config.matrix_username == sender {
-                                log::debug!("Ignoring message sent by the bot user");
+                            let description = format!("reaction {}", event.key);
+                            if !accept_command_source(&config, &event.room, &event.sender, event.timestamp, &description) {
                                 continue;
                             }
 
-                            log::info!("Message from Matrix: {}", event.body);
-                            match event.try_into() {
-                                Ok::<CommandEvent, _>(cmd_event) => {
-                                    if cmd_event.cmd.station_name == config.station_name {
-                                        crate::send_event!(tx, Event::CommandReceive(cmd_event));
-                                    } else {
-                                        log::debug!(
-                                            "Ignoring command with unknown station name: {}",
-                                            cmd_event.cmd.station_name
-                                        );
-                                    }
+                            match config.commands.reactions.iter().find(|r| r.matches(&event.key)) {
+                                Some(r) => {
+                                    log::info!("Reaction from Matrix: {} ({})", event.key, r.op);
+                                    crate::send_event!(
+                                        tx,
+                                        Event::CommandReceive(CommandEvent {
+                                            origin: ResponseTarget {
+                                                event_id: event.relates_to.clone(),
+                                                thread_root: Some(event.relates_to),
+                                            }
+                                            .origin(&event.room),
+                                            sender: event.sender,
+                                            cmd: Command {
+                                                station_name: config.station.name.clone(),
+                                                op: r.op.clone(),
+                                            },
+                                        })
+                                    );
                                 }
-                                Err(e) => {
-                                    log::error!("Failed to parse command from message because {}", e);
-                                    matrix_client
-                                        .get_joined_room(&room)
-                                        .unwrap()
-                                        .send(
-                                            RoomMessageEventContent::text_markdown(format!(
-                                                "{}: That command failed, try `!{} help` for usage details",
-                                                sender, config.station_name
-                                            )),
-                                            None,
-                                        )
-                                        .await
-                                        .unwrap();
+                                None => {
+                                    log::debug!("Ignoring reaction with no configured command: {}", event.key);
                                 }
                             }
                         }
                         Event::CommandReceive(event) => {
+                            if !config.permissions.allows(&event.sender, &event.cmd.op) {
+                                deny(&frontends, &config, &event.origin, &event.sender, &event.cmd.op, None).await;
+                                continue;
+                            }
+
                             log::info!("Processing command: {:?}", event);
-                            COMMANDS
-                                .get_or_create(&CommandLables::new(event.cmd.op.clone()))
-                                .inc();
-                            match event.cmd.op {
-                                Operation::Help => {
-                                    matrix_client
-                                        .get_joined_room(&event.room)
-                                        .unwrap()
-                                        .send(
-                                            RoomMessageEventContent::text_markdown(format!(
-                                                "
-                                                [matrix-remote-closedown](https://github.com/DanNixon/matrix-remote-closedown) for station **{}**.<br>
-                                                Usage: !{} COMMAND<br>
-                                                Commands: help, shutdown, power on, power off, ptt enable, ptt disable",
-                                                config.station_name,
-                                                config.station_name,
-                                                ).unindent()
-                                            ),
-                                            None,
-                                        )
-                                        .await
-                                        .unwrap();
-                                }
-                                Operation::Shutdown => {
-                                    send_command(
-                                        &tx,
-                                        schema::Command {
-                                            enable_tx_power: Some(false),
-                                            enable_ptt: Some(false),
-                                        },
-                                    );
-                                }
-                                Operation::PowerOn => {
-                                    send_command(
-                                        &tx,
-                                        schema::Command {
-                                            enable_tx_power: Some(true),
-                                            enable_ptt: None,
-                                        },
-                                    );
-                                }
-                                Operation::PowerOff => {
-                                    send_command(
-                                        &tx,
-                                        schema::Command {
-                                            enable_tx_power: Some(false),
-                                            enable_ptt: None,
-                                        },
-                                    );
-                                }
-                                Operation::PttEnable => {
-                                    send_command(
-                                        &tx,
-                                        schema::Command {
-                                            enable_tx_power: None,
-                                            enable_ptt: Some(true),
-                                        },
-                                    );
-                                }
-                                Operation::PttDisable => {
-                                    send_command(
-                                        &tx,
-                                        schema::Command {
-                                            enable_tx_power: None,
-                                            enable_ptt: Some(false),
-                                        },
-                                    );
-                                }
+                            perform_operation(
+                                Context {
+                                    tx: &tx,
+                                    frontends: &frontends,
+                                    config: &config,
+                                    transport: transport.as_ref(),
+                                    pending_commands: &mut pending_commands,
+                                },
+                                &event.origin,
+                                &event.sender,
+                                &event.cmd.op,
+                                None,
+                            )
+                            .await;
+                        }
+                        Event::MacroStep(step) => {
+                            // Permissions may have been reloaded since the macro was started
+                            if !config.permissions.allows(&step.sender, &step.op) {
+                                deny(&frontends, &config, &step.origin, &step.sender, &step.op, Some(&step.name)).await;
+                                continue;
                             }
+                            log::info!(
+                                "Processing step `{}` of macro `{}` requested by {}",
+                                step.op,
+                                step.name,
+                                step.sender
+                            );
+                            perform_operation(
+                                Context {
+                                    tx: &tx,
+                                    frontends: &frontends,
+                                    config: &config,
+                                    transport: transport.as_ref(),
+                                    pending_commands: &mut pending_commands,
+                                },
+                                &step.origin,
+                                &step.sender,
+                                &step.op,
+                                Some(&step.name),
+                            )
+                            .await;
+                        }
+                        Event::CommandReplyReceived(reply) => {
+                            resolve_pending_command(&frontends, &config, &mut pending_commands, reply).await;
                         }
-                        Event::MqttStatusMessageReceived(msg) => match serde_json::from_str(&msg) {
-                            Ok::<Response, _>(msg) => {
+                        Event::StatusMessageReceived(msg) => match Response::parse(&msg, config.station.encoding) {
+                            Ok(msg) => {
                                 log::info!("Received response/status message {:?}", msg);
 
-                                if old_status != msg.status {
-                                    send_status_messages(
-                                        &matrix_client,
-                                        &config,
-                                        &format!(
-                                            "
-                                            **{}** at {}<br>
-                                            TX Power: [{}] [{}]<br>
-                                            PTT: [{}] [{}]",
-                                            config.station_name,
-                                            msg.timestamp,
-                                            format_optional_bool!(
-                                                msg.status.tx_power_enabled,
-                                                "ENABLED",
-                                                "DISABLED",
-                                                "unknown"
-                                            ),
-                                            format_optional_bool!(
-                                                msg.status.tx_power_active,
-                                                "ON",
-                                                "OFF",
-                                                "unknown"
-                                            ),
-                                            format_optional_bool!(
-                                                msg.status.ptt_enabled,
-                                                "ENABLED",
-                                                "DISABLED",
-                                                "unknown"
-                                            ),
-                                            format_optional_bool!(
-                                                msg.status.ptt_active,
-                                                "ON AIR",
-                                                "IDLE",
-                                                "unknown"
-                                            ),
-                                        )
-                                        .unindent(),
-                                    )
-                                    .await;
-                                    old_status = msg.status;
+                                confirm_pending_commands(&frontends, &config, &mut pending_commands, &msg).await;
+                                update_status_metrics(&config, &msg.status);
+                                for alert in field_alerts.update(&config.station.name, &config.fields, &msg.status) {
+                                    FIELD_ALERTS.inc();
+                                    frontends.broadcast(&alert).await.log_failures("field alert");
                                 }
 
-                                if let Some(m) = msg.message {
-                                    send_status_messages(
-                                        &matrix_client,
-                                        &config,
-                                        &format!(
-                                            "
-                                            **{}** at {}<br>
-                                            Message: {}",
-                                            config.station_name,
-                                            msg.timestamp,
-                                            m,
-                                        )
-                                        .unindent(),
-                                    )
-                                    .await;
+                                // Other fields (e.g. temperature) change too often to post every change
+                                let status_changed = channel::state_changed(&config.channels, &old_status, &msg.status);
+                                if status_changed || msg.message.is_some() {
+                                    let entry = BufferedStatus {
+                                        timestamp: msg.timestamp,
+                                        status: msg.status.clone(),
+                                        message: msg.message.clone(),
+                                    };
+
+                                    if !status_buffer.is_empty() {
+                                        let ids = flush_status_buffer(&frontends, &config, &mut status_buffer).await;
+                                        remember_status_messages(&mut status_message_ids, ids);
+                                    }
+                                    if status_buffer.is_empty() {
+                                        if let Err(e) = post_status(&frontends, &config, &mut status_message_ids, &msg, status_changed, None).await {
+                                            log::warn!("Failed to post status to Matrix, buffering it ({})", e);
+                                            status_buffer.push(&config.channels, &old_status, entry);
+                                        }
+                                    } else {
+                                        // Matrix is still unreachable, it is sent a summary once it returns
+                                        status_buffer.push(&config.channels, &old_status, entry);
+                                        let _ = post_status(&frontends, &config, &mut status_message_ids, &msg, status_changed, Some(matrix::NAME)).await;
+                                    }
                                 }
+
+                                old_status = msg.status;
                             }
                             Err(e) => {
                                 log::warn!("Failed to parse response from MQTT message, because {}", e);
@@ -236,12 +304,13 @@ pub(crate) fn run_task(
                         },
                     }
                 },
-                event = mqtt_rx.recv() => {
-                    if let Ok(mqtt_channel_client::Event::Rx(msg)) = event {
-                        crate::send_event!(
-                            tx,
-                            Event::MqttStatusMessageReceived(msg.payload_str().to_string())
-                        );
+                _ = reply_timeout_check.tick() => {
+                    expire_pending_commands(&frontends, &config, &mut pending_commands).await;
+                },
+                _ = status_buffer_retry.tick() => {
+                    if !status_buffer.is_empty() {
+                        let ids = flush_status_buffer(&frontends, &config, &mut status_buffer).await;
+                        remember_status_messages(&mut status_message_ids, ids);
                     }
                 },
             }
@@ -249,10 +318,271 @@ pub(crate) fn run_task(
     }))
 }
 
-fn send_command(tx: &Sender<Event>, cmd: schema::Command) {
-    match serde_json::to_string(&cmd) {
-        Ok(cmd) => {
-            crate::send_event!(tx, Event::MqttSendCommandMessage(cmd))
+/// What the processing task lends to an operation being performed.
+struct Context<'a> {
+    tx: &'a Sender<Event>,
+    frontends: &'a Frontends,
+    config: &'a Config,
+    transport: &'a dyn Transport,
+    pending_commands: &'a mut VecDeque<PendingCommand>,
+}
+
+/// Performs an operation that has been requested (and permitted), acknowledging it to the
+/// requester.
+async fn perform_operation(
+    context: Context<'_>,
+    origin: &Origin,
+    sender: &OwnedUserId,
+    op: &Operation,
+    in_macro: Option<&str>,
+) {
+    let Context {
+        tx,
+        frontends,
+        config,
+        transport,
+        pending_commands,
+    } = context;
+
+    let action = match action::resolve(config, op) {
+        Ok(Action::Macro(_)) if in_macro.is_some() => {
+            Err(anyhow!("Macros cannot be run from other macros"))
+        }
+        result => result,
+    };
+    let action = match action {
+        Ok(action) => action,
+        Err(e) => {
+            log::warn!("Rejected command {} ({})", op, e);
+            if let Err(e) = frontends
+                .refuse(
+                    origin,
+                    &format!(
+                        "{}, try `!{} help` for usage details",
+                        e, config.station.name
+                    ),
+                    Refusal::Invalid,
+                )
+                .await
+            {
+                log::warn!("Failed to send command error message ({})", e);
+            }
+            return;
+        }
+    };
+
+    // A macro may only be run by someone permitted to use each of its steps
+    if let Action::Macro(steps) = &action {
+        let forbidden = steps.iter().find_map(|step| match step {
+            Step::Run(step_op) if !config.permissions.allows(sender, step_op) => Some(step_op),
+            _ => None,
+        });
+        if let Some(step_op) = forbidden {
+            deny(
+                frontends,
+                config,
+                origin,
+                sender,
+                step_op,
+                Some(&op.to_string()),
+            )
+            .await;
+            return;
+        }
+    }
+
+    COMMANDS
+        .get_or_create(&CommandLables::new(op.clone()))
+        .inc();
+
+    let correlation_id = new_correlation_id();
+    let acknowledgement = match &action {
+        Action::Help => {
+            if let Err(e) = frontends.reply(origin, &help(config)).await {
+                log::warn!("Failed to send help message ({})", e);
+            }
+            return;
+        }
+        Action::Station(cmd) => {
+            send_command(tx, &config.station, cmd, &correlation_id);
+            format!("Sending `{}` to **{}**", op, config.station.name)
+        }
+        Action::Custom { topic, payload } => {
+            crate::send_event!(
+                tx,
+                Event::SendCommandMessage(CommandMessage {
+                    correlation_id: correlation_id.clone(),
+                    topic: topic.clone(),
+                    payload: payload.clone(),
+                })
+            );
+            format!("Sent `{}` to **{}**", op, config.station.name)
+        }
+        Action::Macro(steps) => {
+            tokio::spawn(run_macro(
+                tx.clone(),
+                origin.clone(),
+                sender.clone(),
+                op.to_string(),
+                steps.clone(),
+            ));
+            format!(
+                "Running `{}` on **{}**: {}",
+                op,
+                config.station.name,
+                steps
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+    };
+    let acknowledgement = match in_macro {
+        Some(name) => format!("{} (macro `{}`)", acknowledgement, name),
+        None => acknowledgement,
+    };
+
+    match frontends.reply(origin, &acknowledgement).await {
+        Ok(message) => {
+            if let Action::Station(cmd) = action {
+                if pending_commands.len() >= PENDING_COMMAND_HISTORY {
+                    pending_commands.pop_front();
+                }
+                pending_commands.push_back(PendingCommand {
+                    op: op.clone(),
+                    cmd,
+                    message,
+                    correlation_id,
+                    sent: Instant::now(),
+                    awaiting_reply: transport.correlates_replies(),
+                });
+            }
+        }
+        Err(e) => {
+            log::warn!("Failed to send command acknowledgement ({})", e);
+        }
+    }
+}
+
+fn help(config: &Config) -> String {
+    let mut help = format!(
+        "
+        [matrix-remote-closedown](https://github.com/DanNixon/matrix-remote-closedown) for station **{}**.<br>
+        Usage: !{} COMMAND<br>
+        Commands: {}",
+        config.station.name,
+        config.station.name,
+        ["help".to_string(), "shutdown".to_string()]
+            .into_iter()
+            .chain(channel::commands(&config.channels))
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+    .unindent();
+
+    let custom = config
+        .operations
+        .iter()
+        .map(|o| (&o.name, &o.description))
+        .chain(config.macros.iter().map(|m| (&m.name, &m.description)))
+        .map(|(name, description)| match description {
+            Some(d) => format!("{} ({})", name, d),
+            None => name.to_string(),
+        })
+        .collect::<Vec<_>>();
+    if !custom.is_empty() {
+        help.push_str(&format!("<br>Custom commands: {}", custom.join(", ")));
+    }
+
+    if !config.commands.reactions.is_empty() {
+        help.push_str(&format!(
+            "<br>Reactions to status messages: {}",
+            config
+                .commands
+                .reactions
+                .iter()
+                .map(|r| format!("{} {}", r.key, r.op))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+
+    help
+}
+
+/// Refuses an operation that the sender is not permitted to use, telling them so.
+async fn deny(
+    frontends: &Frontends,
+    config: &Config,
+    origin: &Origin,
+    sender: &OwnedUserId,
+    op: &Operation,
+    in_macro: Option<&str>,
+) {
+    let context = in_macro
+        .map(|name| format!(" (macro `{}`)", name))
+        .unwrap_or_default();
+    log::warn!("{} is not permitted to request {}{}", sender, op, context);
+    DENIED_COMMANDS.inc();
+    if let Err(e) = frontends
+        .refuse(
+            origin,
+            &format!(
+                "You are not permitted to use `{}` on **{}**{}",
+                op, config.station.name, context
+            ),
+            Refusal::Denied,
+        )
+        .await
+    {
+        log::warn!("Failed to send permission denied message ({})", e);
+    }
+}
+
+/// Runs the steps of a macro, sending each operation back to be performed when it is due.
+async fn run_macro(
+    tx: Sender<Event>,
+    origin: Origin,
+    sender: OwnedUserId,
+    name: String,
+    steps: Vec<Step>,
+) {
+    for step in steps {
+        match step {
+            Step::Wait(duration) => tokio::time::sleep(duration).await,
+            Step::Run(op) => crate::send_event!(
+                tx,
+                Event::MacroStep(MacroStepEvent {
+                    origin: origin.clone(),
+                    sender: sender.clone(),
+                    name: name.clone(),
+                    op,
+                })
+            ),
+        }
+    }
+}
+
+fn send_command(
+    tx: &Sender<Event>,
+    station: &StationConfig,
+    cmd: &schema::Command,
+    correlation_id: &str,
+) {
+    match station
+        .encoding
+        .encode(&cmd.clone().with_version(station.schema_version))
+    {
+        Ok(payload) => {
+            crate::send_event!(
+                tx,
+                Event::SendCommandMessage(CommandMessage {
+                    correlation_id: correlation_id.to_string(),
+                    topic: None,
+                    payload,
+                })
+            )
         }
         Err(e) => {
             log::error!("Failed to serialise command message because {}", e);
@@ -260,13 +590,354 @@ fn send_command(tx: &Sender<Event>, cmd: schema::Command) {
     }
 }
 
-async fn send_status_messages(matrix_client: &matrix_sdk::Client, config: &Cli, body: &str) {
-    for room in &config.matrix_rooms {
-        matrix_client
-            .get_joined_room(room)
-            .unwrap()
-            .send(RoomMessageEventContent::text_markdown(body), None)
+/// Parses the text of a command request, replying with an error if it is not a valid command.
+async fn request_command(
+    tx: &Sender<Event>,
+    frontends: &Frontends,
+    config: &Config,
+    origin: Origin,
+    sender: OwnedUserId,
+    text: String,
+) {
+    match Command::try_from(text) {
+        Ok(cmd) => {
+            if cmd.station_name == config.station.name {
+                crate::send_event!(
+                    tx,
+                    Event::CommandReceive(CommandEvent {
+                        origin,
+                        sender,
+                        cmd
+                    })
+                );
+            } else {
+                log::debug!(
+                    "Ignoring command with unknown station name: {}",
+                    cmd.station_name
+                );
+            }
+        }
+        Err(e) => {
+            log::error!("Failed to parse command from message because {}", e);
+            if let Err(e) = frontends
+                .refuse(
+                    &origin,
+                    &format!(
+                        "That command failed, try `!{} help` for usage details",
+                        config.station.name
+                    ),
+                    Refusal::Invalid,
+                )
+                .await
+            {
+                log::warn!("Failed to send command error message ({})", e);
+            }
+        }
+    }
+}
+
+/// Checks that a command request came from somewhere it should be accepted from.
+fn accept_command_source(
+    config: &Config,
+    room: &OwnedRoomId,
+    sender: &OwnedUserId,
+    timestamp: DateTime<Local>,
+    description: &str,
+) -> bool {
+    if !config.rooms.contains(room) {
+        log::debug!("Ignoring command in room we do not watch");
+        return false;
+    }
+
+    if config.matrix.username == *sender {
+        log::debug!("Ignoring command sent by the bot user");
+        return false;
+    }
+
+    let age = Local::now().signed_duration_since(timestamp);
+    if age.num_seconds().unsigned_abs() > config.commands.max_age {
+        log::warn!(
+            "Ignoring stale command from {} sent at {} ({}s old): {}",
+            sender,
+            timestamp,
+            age.num_seconds(),
+            description
+        );
+        STALE_COMMANDS.inc();
+        return false;
+    }
+
+    true
+}
+
+/// Edits the acknowledgements of commands that a status update shows have taken effect.
+///
+/// Commands the station replies to directly are left to `resolve_pending_command`.
+async fn confirm_pending_commands(
+    frontends: &Frontends,
+    config: &Config,
+    pending_commands: &mut VecDeque<PendingCommand>,
+    msg: &Response,
+) {
+    let (confirmed, waiting): (Vec<_>, VecDeque<_>) = pending_commands.drain(..).partition(|p| {
+        !p.awaiting_reply && channel::is_satisfied_by(&config.channels, &p.cmd, &msg.status)
+    });
+    *pending_commands = waiting;
+
+    for p in confirmed {
+        let body = format!(
+            "Sent `{}` to **{}**, confirmed at {}",
+            p.op,
+            config.station.name,
+            msg.timestamp.format("%H:%M:%S")
+        );
+        finish_pending_command(frontends, p, Outcome::Confirmed, &body).await;
+    }
+}
+
+/// Edits the acknowledgement of the command a reply from the station is for, reporting whether
+/// it took effect.
+async fn resolve_pending_command(
+    frontends: &Frontends,
+    config: &Config,
+    pending_commands: &mut VecDeque<PendingCommand>,
+    reply: CommandReplyEvent,
+) {
+    let Some(index) = pending_commands
+        .iter()
+        .position(|p| p.correlation_id == reply.correlation_id)
+    else {
+        log::debug!(
+            "Ignoring reply to unknown or expired command {}",
+            reply.correlation_id
+        );
+        return;
+    };
+
+    let msg = match Response::parse(&reply.payload, config.station.encoding) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::warn!("Failed to parse reply to command, because {}", e);
+            return;
+        }
+    };
+    log::info!(
+        "Received reply to command {}: {:?}",
+        reply.correlation_id,
+        msg
+    );
+
+    let p = pending_commands.remove(index).unwrap();
+    let (outcome, mut body) = if channel::is_satisfied_by(&config.channels, &p.cmd, &msg.status) {
+        (
+            Outcome::Confirmed,
+            format!(
+                "Sent `{}` to **{}**, confirmed at {}",
+                p.op,
+                config.station.name,
+                msg.timestamp.format("%H:%M:%S")
+            ),
+        )
+    } else {
+        (
+            Outcome::NotApplied,
+            format!(
+                "Sent `{}` to **{}**, but it was not applied (replied at {})",
+                p.op,
+                config.station.name,
+                msg.timestamp.format("%H:%M:%S")
+            ),
+        )
+    };
+    if let Some(message) = &msg.message {
+        body.push_str(&format!("<br>Station says: {}", message));
+    }
+    finish_pending_command(frontends, p, outcome, &body).await;
+}
+
+/// Edits the acknowledgements of commands that the station has not replied to in time, or when it
+/// does not reply to commands, that its status has not shown to take effect in time.
+async fn expire_pending_commands(
+    frontends: &Frontends,
+    config: &Config,
+    pending_commands: &mut VecDeque<PendingCommand>,
+) {
+    let timeout = Duration::from_secs(config.commands.reply_timeout);
+    let (expired, waiting): (Vec<_>, VecDeque<_>) = pending_commands
+        .drain(..)
+        .partition(|p| p.sent.elapsed() >= timeout);
+    *pending_commands = waiting;
+
+    for p in expired {
+        let body = if p.awaiting_reply {
+            log::warn!("No reply to command {} ({})", p.op, p.correlation_id);
+            format!(
+                "Sent `{}` to **{}**, but got no reply within {}s",
+                p.op,
+                config.station.name,
+                timeout.as_secs()
+            )
+        } else {
+            log::warn!("Command {} not seen to take effect", p.op);
+            format!(
+                "Sent `{}` to **{}**, but it was not seen to take effect within {}s",
+                p.op,
+                config.station.name,
+                timeout.as_secs()
+            )
+        };
+        finish_pending_command(frontends, p, Outcome::TimedOut, &body).await;
+    }
+}
+
+async fn finish_pending_command(
+    frontends: &Frontends,
+    p: PendingCommand,
+    outcome: Outcome,
+    body: &str,
+) {
+    COMMAND_OUTCOMES
+        .get_or_create(&OutcomeLabels::new(p.op, outcome))
+        .inc();
+    if let Err(e) = frontends.edit(&p.message, body).await {
+        log::warn!("Failed to edit command acknowledgement ({})", e);
+    }
+}
+
+async fn post_relayed_message(frontends: &Frontends, message: RelayedMessage) {
+    RELAYED_MESSAGES.inc();
+
+    if message.rooms.is_empty() {
+        frontends
+            .broadcast(&message.body)
             .await
-            .unwrap();
+            .log_failures("relayed message");
+        return;
+    }
+
+    for room in message.rooms {
+        let origin = Origin {
+            frontend: matrix::NAME,
+            channel: room.to_string(),
+            message: None,
+            thread: None,
+        };
+        if let Err(e) = frontends.reply(&origin, &message.body).await {
+            log::warn!("Failed to post relayed message to {} ({})", room, e);
+        }
+    }
+}
+
+fn remember_status_messages(status_message_ids: &mut VecDeque<MessageRef>, ids: Vec<MessageRef>) {
+    for id in ids {
+        if status_message_ids.len() >= STATUS_MESSAGE_HISTORY {
+            status_message_ids.pop_front();
+        }
+        status_message_ids.push_back(id);
+    }
+}
+
+/// Posts a status update via every frontend (other than `skip`), failing only if Matrix could not
+/// be posted to, as that is what the status buffer is for.
+async fn post_status(
+    frontends: &Frontends,
+    config: &Config,
+    status_message_ids: &mut VecDeque<MessageRef>,
+    msg: &Response,
+    status_changed: bool,
+    skip: Option<&str>,
+) -> Result<()> {
+    let mut result = Ok(());
+
+    let station = config.station.name.as_str();
+    let timestamp = msg.timestamp.to_string();
+
+    if status_changed {
+        let channels = channel::status_summary(&config.channels, &msg.status);
+        let field_summary = fields::summary(&config.fields, &msg.status);
+        let field_values = fields::values(&config.fields, &msg.status);
+        let mut values = vec![
+            ("station", station),
+            ("timestamp", timestamp.as_str()),
+            ("channels", channels.as_str()),
+            ("fields", field_summary.as_str()),
+        ];
+        values.extend(channel::status_values(&config.channels, &msg.status));
+        values.extend(field_values.iter().map(|(k, v)| (*k, v.as_str())));
+        let body = config
+            .templates
+            .status
+            .render_or(&Templates::default().status, &values);
+        let sent = frontends.broadcast_except(skip, &body).await;
+        sent.log_failures("status");
+        result = sent.check(matrix::NAME);
+        remember_status_messages(status_message_ids, sent.ids);
+    }
+
+    if let Some(m) = &msg.message {
+        let body = config.templates.message.render_or(
+            &Templates::default().message,
+            &[
+                ("station", station),
+                ("timestamp", &timestamp),
+                ("message", m),
+            ],
+        );
+        let sent = frontends.broadcast_except(skip, &body).await;
+        sent.log_failures("status message");
+        result = result.and(sent.check(matrix::NAME));
+    }
+
+    result
+}
+
+fn update_status_metrics(config: &Config, status: &Status) {
+    for c in &config.channels {
+        let fields = [
+            (ChannelField::Enabled, Some(&c.status_field)),
+            (ChannelField::Active, c.active_field.as_ref()),
+        ];
+        for (field, name) in fields {
+            if let Some(value) = name.and_then(|name| status.get(name)) {
+                CHANNEL_STATE
+                    .get_or_create(&ChannelLabels::new(&c.name, field))
+                    .set(value as i64);
+            }
+        }
+    }
+
+    for (field, value) in fields::numeric(&config.fields, status) {
+        STATUS_FIELDS
+            .get_or_create(&StatusFieldLabels::new(field))
+            .set(value);
+    }
+}
+
+/// Posts a summary of the buffered status updates to Matrix, which the other frontends were sent
+/// as they arrived.
+async fn flush_status_buffer(
+    frontends: &Frontends,
+    config: &Config,
+    status_buffer: &mut StatusBuffer,
+) -> Vec<MessageRef> {
+    match frontends
+        .broadcast_via(
+            matrix::NAME,
+            &status_buffer.summary(&config.station.name, &config.channels),
+        )
+        .await
+    {
+        Ok(ids) => {
+            log::info!("Posted summary of buffered status updates");
+            status_buffer.clear();
+            ids
+        }
+        Err(e) => {
+            log::warn!(
+                "Matrix still unreachable, keeping buffered status updates ({})",
+                e
+            );
+            Vec::new()
+        }
     }
 }
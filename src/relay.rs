@@ -0,0 +1,281 @@
+use crate::config::RelayConfig;
+use anyhow::{anyhow, Error};
+use chrono::{offset::Local, DateTime, Duration};
+use matrix_sdk::ruma::OwnedRoomId;
+use serde::Deserialize;
+use std::{fmt, str::FromStr};
+
+/// How important a relayed message is, messages without one are treated as `Info`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+#[serde(try_from = "String")]
+pub(crate) enum Severity {
+    Debug,
+    #[default]
+    Info,
+    Warning,
+    Error,
+    Critical,
+}
+
+impl FromStr for Severity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warning" | "warn" => Ok(Self::Warning),
+            "error" | "err" => Ok(Self::Error),
+            "critical" | "crit" => Ok(Self::Critical),
+            _ => Err(anyhow!("Unknown severity \"{}\"", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Severity {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Debug => write!(f, "debug"),
+            Self::Info => write!(f, "info"),
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+            Self::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// Checks if an MQTT topic matches a topic filter (which may contain `+` and `#` wildcards).
+pub(crate) fn topic_matches(filter: &str, topic: &str) -> bool {
+    let mut topic_levels = topic.split('/');
+    for level in filter.split('/') {
+        match (level, topic_levels.next()) {
+            ("#", _) => return true,
+            ("+", Some(_)) => {}
+            (level, Some(t)) if level == t => {}
+            _ => return false,
+        }
+    }
+    topic_levels.next().is_none()
+}
+
+/// A message to post on behalf of a relay.
+#[derive(Debug, PartialEq)]
+pub(crate) struct RelayedMessage {
+    /// Rooms to post to, all rooms (and other frontends) if empty
+    pub rooms: Vec<OwnedRoomId>,
+    pub body: String,
+}
+
+#[derive(Clone, Default)]
+struct RelayState {
+    last_sent: Option<DateTime<Local>>,
+    /// Messages not relayed since the last one that was, due to rate limiting
+    suppressed: usize,
+}
+
+/// Turns messages received on relayed topics into messages to post, applying each relay's
+/// severity filter and rate limit.
+#[derive(Default)]
+pub(crate) struct Relays {
+    state: Vec<RelayState>,
+}
+
+impl Relays {
+    pub(crate) fn handle(
+        &mut self,
+        station: &str,
+        relays: &[RelayConfig],
+        topic: &str,
+        payload: &str,
+        now: DateTime<Local>,
+    ) -> Vec<RelayedMessage> {
+        // Relays may have been changed by a reload, their state cannot be carried over
+        if self.state.len() != relays.len() {
+            self.state = vec![RelayState::default(); relays.len()];
+        }
+
+        let json: Option<serde_json::Value> = serde_json::from_str(payload).ok();
+        let mut messages = Vec::new();
+
+        for (relay, state) in relays.iter().zip(self.state.iter_mut()) {
+            if !topic_matches(&relay.topic, topic) {
+                continue;
+            }
+
+            let severity: Severity = relay
+                .severity_field
+                .as_deref()
+                .and_then(|pointer| json.as_ref()?.pointer(pointer))
+                .and_then(|v| v.as_str()?.parse().ok())
+                .unwrap_or_default();
+            if relay.min_severity.is_some_and(|min| severity < min) {
+                log::debug!("Not relaying {} message on {}", severity, topic);
+                continue;
+            }
+
+            if state
+                .last_sent
+                .is_some_and(|last| now - last < Duration::seconds(relay.min_interval as i64))
+            {
+                log::debug!("Rate limiting message on {}", topic);
+                state.suppressed += 1;
+                continue;
+            }
+
+            let fields: Vec<(&str, String)> = relay
+                .fields
+                .iter()
+                .map(|(name, pointer)| {
+                    let value = match json.as_ref().and_then(|j| j.pointer(pointer)) {
+                        Some(serde_json::Value::String(s)) => s.clone(),
+                        Some(v) => v.to_string(),
+                        None => String::new(),
+                    };
+                    (name.as_str(), value)
+                })
+                .collect();
+
+            let timestamp = now.to_string();
+            let severity = severity.to_string();
+            let mut values = vec![
+                ("station", station),
+                ("timestamp", timestamp.as_str()),
+                ("topic", topic),
+                ("payload", payload),
+                ("severity", severity.as_str()),
+            ];
+            values.extend(fields.iter().map(|(k, v)| (*k, v.as_str())));
+
+            match relay.template.render(&values) {
+                Ok(mut body) => {
+                    if state.suppressed > 0 {
+                        body.push_str(&format!(
+                            "<br>({} earlier messages not relayed)",
+                            state.suppressed
+                        ));
+                    }
+                    state.last_sent = Some(now);
+                    state.suppressed = 0;
+                    messages.push(RelayedMessage {
+                        rooms: relay.rooms.clone(),
+                        body,
+                    });
+                }
+                Err(e) => log::warn!("Failed to render relay template for {} ({})", topic, e),
+            }
+        }
+
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::Template;
+    use chrono::TimeZone;
+
+    fn at(secs: i64) -> DateTime<Local> {
+        Local.timestamp_opt(1_700_000_000 + secs, 0).unwrap()
+    }
+
+    fn relay() -> RelayConfig {
+        RelayConfig {
+            topic: "mb7pmf/alarms/+".to_string(),
+            rooms: vec![],
+            template: Template::new("**{station}** {severity}: {text}"),
+            fields: [("text".to_string(), "/alarm/text".to_string())].into(),
+            severity_field: Some("/level".to_string()),
+            min_severity: Some(Severity::Warning),
+            min_interval: 60,
+        }
+    }
+
+    #[test]
+    fn topics() {
+        assert!(topic_matches("a/b", "a/b"));
+        assert!(!topic_matches("a/b", "a/b/c"));
+        assert!(!topic_matches("a/b/c", "a/b"));
+        assert!(topic_matches("a/+/c", "a/b/c"));
+        assert!(!topic_matches("a/+", "a/b/c"));
+        assert!(topic_matches("a/#", "a/b/c"));
+        assert!(topic_matches("a/#", "a"));
+        assert!(topic_matches("#", "a/b"));
+    }
+
+    #[test]
+    fn severities() {
+        assert_eq!("WARN".parse::<Severity>().unwrap(), Severity::Warning);
+        assert!(Severity::Critical > Severity::Error);
+        assert!("loud".parse::<Severity>().is_err());
+    }
+
+    #[test]
+    fn relays_messages() {
+        let relays = [relay()];
+        let mut state = Relays::default();
+
+        assert_eq!(
+            state.handle(
+                "mb7pmf",
+                &relays,
+                "mb7pmf/alarms/door",
+                r#"{"level": "error", "alarm": {"text": "door open"}}"#,
+                at(0)
+            ),
+            vec![RelayedMessage {
+                rooms: vec![],
+                body: "**mb7pmf** error: door open".to_string()
+            }]
+        );
+
+        assert!(state
+            .handle("mb7pmf", &relays, "mb7pmf/logs", "{}", at(100))
+            .is_empty());
+    }
+
+    #[test]
+    fn filters_severity() {
+        let relays = [relay()];
+        let mut state = Relays::default();
+        let handle = |state: &mut Relays, payload| {
+            state.handle("mb7pmf", &relays, "mb7pmf/alarms/door", payload, at(0))
+        };
+
+        assert!(handle(&mut state, r#"{"level": "info"}"#).is_empty());
+        // No severity is treated as info
+        assert!(handle(&mut state, "not json").is_empty());
+        assert_eq!(handle(&mut state, r#"{"level": "warning"}"#).len(), 1);
+    }
+
+    #[test]
+    fn rate_limits() {
+        let relays = [relay()];
+        let mut state = Relays::default();
+        let mut handle = |secs| {
+            state.handle(
+                "mb7pmf",
+                &relays,
+                "mb7pmf/alarms/door",
+                r#"{"level": "error"}"#,
+                at(secs),
+            )
+        };
+
+        assert_eq!(handle(0).len(), 1);
+        assert!(handle(10).is_empty());
+        assert!(handle(20).is_empty());
+        assert_eq!(
+            handle(60)[0].body,
+            "**mb7pmf** error: <br>(2 earlier messages not relayed)"
+        );
+    }
+}
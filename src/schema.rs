@@ -1,23 +1,282 @@
+use crate::{
+    config::ChannelConfig,
+    encoding::{self, Encoding},
+};
+use anyhow::Result;
 use chrono::{offset::Local, DateTime};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
-#[derive(Default, Debug, Deserialize, PartialEq)]
-pub(crate) struct Status {
-    pub tx_power_enabled: Option<bool>,
-    pub tx_power_active: Option<bool>,
-    pub ptt_enabled: Option<bool>,
-    pub ptt_active: Option<bool>,
+/// Versions of the message format that are understood.
+///
+/// Version 1 messages have no `version` field, later versions give it explicitly.
+pub(crate) const SUPPORTED_VERSIONS: &[u32] = &[1, 2];
+
+/// Last unsupported version warned about, so that every message does not repeat the warning
+static WARNED_VERSION: AtomicU32 = AtomicU32::new(0);
+
+/// Status fields reported by the station, keyed by field name (e.g. `tx_power_enabled`).
+#[derive(Clone, Default, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub(crate) struct Status(pub BTreeMap<String, serde_json::Value>);
+
+impl Status {
+    /// The value of a boolean field, `None` if it is missing or not a boolean.
+    pub(crate) fn get(&self, field: &str) -> Option<bool> {
+        self.0.get(field).and_then(serde_json::Value::as_bool)
+    }
+
+    pub(crate) fn set(&mut self, field: &str, value: bool) {
+        self.0.insert(field.to_string(), value.into());
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct Response {
+    /// Format version, absent in version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     pub status: Status,
     pub message: Option<String>,
     pub timestamp: DateTime<Local>,
 }
 
-#[derive(Debug, Serialize)]
+impl Response {
+    /// Parses a status message or reply from the station, warning if it is of an unknown version
+    /// (which is still parsed as well as possible).
+    ///
+    /// `encoding` is assumed if that of the message cannot be detected.
+    pub(crate) fn parse(payload: &[u8], encoding: Encoding) -> Result<Self> {
+        let response: Self = encoding::decode(payload, encoding)?;
+        let version = response.version.unwrap_or(1);
+        if !SUPPORTED_VERSIONS.contains(&version)
+            && WARNED_VERSION.swap(version, Ordering::Relaxed) != version
+        {
+            log::warn!(
+                "Station sent a message of unknown format version {} (supported: {:?})",
+                version,
+                SUPPORTED_VERSIONS
+            );
+        }
+        Ok(response)
+    }
+}
+
+/// Command fields to send to the station, keyed by field name (e.g. `enable_tx_power`).
+///
+/// Fields of channels that are left as they are have no value.
+#[derive(Clone, Default, Debug, Deserialize, PartialEq, Serialize)]
 pub(crate) struct Command {
-    pub enable_tx_power: Option<bool>,
-    pub enable_ptt: Option<bool>,
+    /// Format version, absent in version 1
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, Option<bool>>,
+}
+
+impl Command {
+    /// Field values to set, as pairs of command field and value.
+    pub(crate) fn values(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.fields
+            .iter()
+            .filter_map(|(field, value)| Some((field.as_str(), (*value)?)))
+    }
+
+    /// Sets the format version to send the command in.
+    pub(crate) fn with_version(mut self, version: u32) -> Self {
+        self.version = (version > 1).then_some(version);
+        self
+    }
+}
+
+/// A message that a JSON Schema can be generated for.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum Message {
+    Command,
+    Response,
+}
+
+/// Generates a JSON Schema describing a message of the given format version, for the
+/// configured channels.
+pub(crate) fn json_schema(
+    message: Message,
+    version: u32,
+    channels: &[ChannelConfig],
+) -> serde_json::Value {
+    let version_property = json!({
+        "description": "Message format version",
+        "const": version,
+    });
+
+    let (title, mut properties, mut required) = match message {
+        Message::Command => (
+            "Command",
+            channels
+                .iter()
+                .map(|c| {
+                    (
+                        c.command_field.clone(),
+                        json!({
+                            "description": format!(
+                                "{}: true to enable, false to disable, null to leave as it is",
+                                c.label
+                            ),
+                            "type": ["boolean", "null"],
+                        }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>(),
+            Vec::new(),
+        ),
+        Message::Response => {
+            let status = channels
+                .iter()
+                .flat_map(|c| {
+                    [
+                        Some((&c.status_field, format!("{}: enabled", c.label))),
+                        c.active_field
+                            .as_ref()
+                            .map(|f| (f, format!("{}: active", c.label))),
+                    ]
+                })
+                .flatten()
+                .map(|(field, description)| {
+                    (
+                        field.clone(),
+                        json!({ "description": description, "type": "boolean" }),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>();
+            (
+                "Response",
+                json!({
+                    "status": {
+                        "description": "State of the station, other fields (e.g. sensor readings) may be included",
+                        "type": "object",
+                        "properties": status,
+                        "additionalProperties": true,
+                    },
+                    "message": {
+                        "description": "Text to post to the rooms",
+                        "type": ["string", "null"],
+                    },
+                    "timestamp": {
+                        "type": "string",
+                        "format": "date-time",
+                    },
+                })
+                .as_object()
+                .cloned()
+                .unwrap_or_default(),
+                vec!["status", "timestamp"],
+            )
+        }
+    };
+
+    if version > 1 {
+        properties.insert("version".to_string(), version_property);
+        required.push("version");
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "$id": format!(
+            "urn:matrix-remote-closedown:v{}:{}",
+            version,
+            title.to_lowercase()
+        ),
+        "title": title,
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "additionalProperties": message == Message::Response,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::default_channels;
+
+    #[test]
+    fn wire_format() {
+        let status: Status =
+            serde_json::from_str(r#"{"tx_power_enabled": true, "temperature": 21.5}"#).unwrap();
+        assert_eq!(status.get("tx_power_enabled"), Some(true));
+        assert_eq!(status.get("temperature"), None);
+        assert_eq!(status.get("ptt_enabled"), None);
+
+        let cmd = Command {
+            version: None,
+            fields: [
+                ("enable_ptt".to_string(), None),
+                ("enable_tx_power".to_string(), Some(false)),
+            ]
+            .into(),
+        };
+        assert_eq!(
+            serde_json::to_string(&cmd).unwrap(),
+            r#"{"enable_ptt":null,"enable_tx_power":false}"#
+        );
+        assert_eq!(
+            cmd.values().collect::<Vec<_>>(),
+            [("enable_tx_power", false)]
+        );
+    }
+
+    #[test]
+    fn versions() {
+        let cmd = Command {
+            version: None,
+            fields: [("enable_ptt".to_string(), Some(true))].into(),
+        };
+        let payload = serde_json::to_string(&cmd.clone().with_version(2)).unwrap();
+        assert_eq!(payload, r#"{"version":2,"enable_ptt":true}"#);
+        assert_eq!(
+            serde_json::from_str::<Command>(&payload).unwrap().version,
+            Some(2)
+        );
+        assert_eq!(cmd.with_version(1).version, None);
+
+        let v1 = r#"{"status": {}, "message": null, "timestamp": "2024-01-01T00:00:00Z"}"#;
+        assert_eq!(
+            Response::parse(v1.as_bytes(), Encoding::Json)
+                .unwrap()
+                .version,
+            None
+        );
+        let v3 = r#"{"version": 3, "status": {}, "timestamp": "2024-01-01T00:00:00Z"}"#;
+        assert_eq!(
+            Response::parse(v3.as_bytes(), Encoding::Json)
+                .unwrap()
+                .version,
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn schemas() {
+        let schema = json_schema(Message::Command, 1, &default_channels());
+        assert_eq!(
+            schema["properties"]["enable_ptt"]["type"],
+            json!(["boolean", "null"])
+        );
+        assert_eq!(schema["additionalProperties"], json!(false));
+        assert!(schema["properties"].get("version").is_none());
+
+        let schema = json_schema(Message::Response, 2, &default_channels());
+        assert_eq!(
+            schema["properties"]["status"]["properties"]["ptt_active"]["type"],
+            "boolean"
+        );
+        assert_eq!(
+            schema["required"],
+            json!(["status", "timestamp", "version"])
+        );
+    }
 }
@@ -0,0 +1,73 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::{convert::Infallible, fmt, path::Path, str::FromStr};
+use subtle::ConstantTimeEq;
+
+/// A value that must not appear in logs, its `Debug` output is redacted.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub(crate) struct Secret(String);
+
+impl Secret {
+    /// Reads a secret from a file (e.g. a systemd credential or container secret), ignoring any
+    /// trailing newline.
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let value = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read secret from {}", path.display()))?;
+        Ok(Self(value.trim_end_matches(['\r', '\n']).to_string()))
+    }
+
+    pub(crate) fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+impl FromStr for Secret {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// Whether a token presented by a client is the expected one, compared in constant time so that
+/// response times do not reveal how much of it is correct. An empty token never matches.
+pub(crate) fn token_matches(expected: &str, presented: &str) -> bool {
+    !expected.is_empty() && bool::from(expected.as_bytes().ct_eq(presented.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_is_redacted() {
+        let secret: Secret = "hunter2".parse().unwrap();
+        assert_eq!(format!("{:?}", secret), "<redacted>");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn read_trims_newline() {
+        let path = std::env::temp_dir().join(format!(
+            "matrix-remote-closedown-secret-test-{}",
+            std::process::id()
+        ));
+        std::fs::write(&path, "hunter2\n").unwrap();
+        assert_eq!(Secret::read(&path).unwrap().expose(), "hunter2");
+    }
+
+    #[test]
+    fn token_matching() {
+        assert!(token_matches("hunter2", "hunter2"));
+        assert!(!token_matches("hunter2", "hunter3"));
+        assert!(!token_matches("hunter2", "hunter"));
+        assert!(!token_matches("", ""));
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{
+    channel,
+    command::Operation,
+    config::LinkConfig,
+    event::{CommandReplyEvent, Event},
+    schema::Response,
+    transport,
+};
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// Sends a single command to the station and waits for a status update showing it took effect, or
+/// for the station's reply to it if the transport supports replies.
+pub(crate) async fn run(mut config: LinkConfig, command: &str, timeout: Duration) -> Result<()> {
+    // Availability is that of the bot, not of one-off commands
+    config.mqtt.availability_topic = None;
+
+    let op: Operation = command.parse()?;
+    let cmd = channel::station_command(&config.channels, &op)?
+        .ok_or_else(|| anyhow!("`{}` is not a command that can be sent to a station", op))?;
+
+    let (tx, mut rx) = broadcast::channel::<Event>(16);
+    let transport = transport::connect(
+        &config,
+        &format!("{}-send-{}", config.mqtt.client_id, std::process::id()),
+        tx,
+    )
+    .await?;
+
+    let correlation_id = transport::new_correlation_id();
+    // Status reported before this (e.g. retained by the broker) says nothing about the command
+    let sent_at = Local::now();
+    transport.send_command(
+        config
+            .station
+            .encoding
+            .encode(&cmd.clone().with_version(config.station.schema_version))?,
+        &correlation_id,
+    )?;
+    println!("Sent `{}` to {}", op, config.station.name);
+
+    let wait = async {
+        loop {
+            match rx.recv().await {
+                Ok(Event::CommandReplyReceived(CommandReplyEvent {
+                    correlation_id: id,
+                    payload,
+                })) if id == correlation_id => {
+                    let response = Response::parse(&payload, config.station.encoding)?;
+                    println!("{:?}", response.status);
+                    if let Some(message) = &response.message {
+                        println!("Message: {}", message);
+                    }
+                    return if channel::is_satisfied_by(&config.channels, &cmd, &response.status) {
+                        Ok(())
+                    } else {
+                        Err(anyhow!("{} did not apply the command", config.station.name))
+                    };
+                }
+                Ok(Event::StatusMessageReceived(msg)) if !transport.correlates_replies() => {
+                    match Response::parse(&msg, config.station.encoding) {
+                        Ok(response) if response.timestamp < sent_at => {
+                            log::debug!(
+                                "Ignoring status from {}, before the command was sent",
+                                response.timestamp
+                            );
+                        }
+                        Ok(response) => {
+                            println!("{:?}", response.status);
+                            if let Some(message) = &response.message {
+                                println!("Message: {}", message);
+                            }
+                            if channel::is_satisfied_by(&config.channels, &cmd, &response.status) {
+                                return Ok(());
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("Failed to parse response from station, because {}", e);
+                        }
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return Err(anyhow!("Connection to station closed")),
+            }
+        }
+    };
+
+    match tokio::time::timeout(timeout, wait).await {
+        Ok(result) => result,
+        Err(_) if transport.correlates_replies() => Err(anyhow!(
+            "{} did not reply within {}s",
+            config.station.name,
+            timeout.as_secs()
+        )),
+        Err(_) => Err(anyhow!(
+            "{} did not reach the expected state within {}s",
+            config.station.name,
+            timeout.as_secs()
+        )),
+    }
+}
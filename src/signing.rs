@@ -0,0 +1,145 @@
+use crate::config::SigningConfig;
+use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Mutex};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A message wrapped with an HMAC-SHA256 signature over its timestamp, nonce and payload.
+///
+/// The payload is carried as a string so that the signed bytes are exactly those received,
+/// regardless of how the JSON inside it would be re-serialised.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Envelope {
+    pub payload: String,
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    pub nonce: String,
+    /// Hex encoded HMAC-SHA256
+    pub signature: String,
+}
+
+/// Signs outgoing messages and verifies incoming ones using a key shared with the station.
+pub(crate) struct Signer {
+    key: Vec<u8>,
+    max_age: i64,
+    /// Nonces of recently accepted messages and their timestamps, to reject replays
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl Signer {
+    pub(crate) fn new(config: &SigningConfig) -> Self {
+        Self {
+            key: config
+                .key
+                .as_ref()
+                .map(|k| k.expose().as_bytes().to_vec())
+                .unwrap_or_default(),
+            max_age: config.max_age as i64,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wraps `payload` in a signed envelope, returning the serialised envelope.
+    pub(crate) fn seal(&self, payload: &str) -> Result<String> {
+        self.seal_at(payload, chrono::Utc::now().timestamp())
+    }
+
+    /// Verifies a serialised envelope, returning the payload it carries.
+    pub(crate) fn open(&self, message: &str) -> Result<String> {
+        self.open_at(message, chrono::Utc::now().timestamp())
+    }
+
+    fn seal_at(&self, payload: &str, timestamp: i64) -> Result<String> {
+        let mut nonce = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let nonce = hex::encode(nonce);
+
+        let signature = hex::encode(self.mac(payload, timestamp, &nonce).finalize().into_bytes());
+
+        Ok(serde_json::to_string(&Envelope {
+            payload: payload.to_string(),
+            timestamp,
+            nonce,
+            signature,
+        })?)
+    }
+
+    fn open_at(&self, message: &str, now: i64) -> Result<String> {
+        let envelope: Envelope =
+            serde_json::from_str(message).context("Message is not a signed envelope")?;
+
+        let signature = hex::decode(&envelope.signature).context("Malformed signature")?;
+        self.mac(&envelope.payload, envelope.timestamp, &envelope.nonce)
+            .verify_slice(&signature)
+            .map_err(|_| anyhow!("Invalid signature"))?;
+
+        let age = now - envelope.timestamp;
+        if age.abs() > self.max_age {
+            return Err(anyhow!("Message timestamp is {}s from now", age));
+        }
+
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, timestamp| (now - *timestamp).abs() <= self.max_age);
+        if seen.insert(envelope.nonce, envelope.timestamp).is_some() {
+            return Err(anyhow!("Message has already been received (replay)"));
+        }
+
+        Ok(envelope.payload)
+    }
+
+    fn mac(&self, payload: &str, timestamp: i64, nonce: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(format!("{}\n{}\n{}", timestamp, nonce, payload).as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(key: &str) -> Signer {
+        Signer::new(&SigningConfig {
+            key: Some(key.parse().unwrap()),
+            key_file: None,
+            max_age: 30,
+        })
+    }
+
+    #[test]
+    fn seal_open() {
+        let signer = signer("hunter2");
+        let sealed = signer.seal_at(r#"{"enable_ptt":false}"#, 1000).unwrap();
+        assert_eq!(
+            signer.open_at(&sealed, 1010).unwrap(),
+            r#"{"enable_ptt":false}"#
+        );
+    }
+
+    #[test]
+    fn rejects_forged() {
+        let sealed = signer("hunter2").seal_at("{}", 1000).unwrap();
+        assert!(signer("letmein").open_at(&sealed, 1000).is_err());
+
+        let mut envelope: Envelope = serde_json::from_str(&sealed).unwrap();
+        envelope.payload = r#"{"enable_ptt":true}"#.to_string();
+        let tampered = serde_json::to_string(&envelope).unwrap();
+        assert!(signer("hunter2").open_at(&tampered, 1000).is_err());
+
+        assert!(signer("hunter2").open_at("{}", 1000).is_err());
+    }
+
+    #[test]
+    fn rejects_stale_and_replayed() {
+        let signer = signer("hunter2");
+        let sealed = signer.seal_at("{}", 1000).unwrap();
+        assert!(signer.open_at(&sealed, 1031).is_err());
+        assert!(signer.open_at(&sealed, 1000).is_ok());
+        assert!(signer.open_at(&sealed, 1001).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use crate::{
+    broker,
+    config::{ChannelConfig, LinkConfig, TransportKind},
+    encoding::{self, Encoding},
+    schema::{Command, Response, Status},
+    signing::Signer,
+};
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use clap::ValueEnum;
+use mqtt_channel_client as mqtt;
+use std::time::Duration;
+
+/// Faults that can be injected into a simulated station.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub(crate) enum Fault {
+    /// PTT (the `ptt` channel) is keyed and stays enabled regardless of commands
+    StuckPtt,
+    /// Commands are applied but no response is published
+    NoReply,
+    /// Responses are replaced with payloads that are not valid JSON
+    Garbage,
+}
+
+/// A fake remote closedown device.
+pub(crate) struct Station {
+    channels: Vec<ChannelConfig>,
+    status: Status,
+    faults: Vec<Fault>,
+    /// Format version to respond in, that of the last command received
+    version: Option<u32>,
+    encoding: Encoding,
+}
+
+impl Station {
+    /// Creates a station with every channel enabled, but not active.
+    pub(crate) fn new(channels: Vec<ChannelConfig>, faults: Vec<Fault>) -> Self {
+        let mut status = Status::default();
+        for c in &channels {
+            status.set(&c.status_field, true);
+            if let Some(field) = &c.active_field {
+                status.set(field, false);
+            }
+        }
+
+        let mut station = Self {
+            channels,
+            status,
+            faults,
+            version: None,
+            encoding: Encoding::Json,
+        };
+        station.apply_faults();
+        station
+    }
+
+    /// Applies a command, returning the payload to publish in response (if any).
+    pub(crate) fn handle(&mut self, cmd: &Command) -> Option<Vec<u8>> {
+        self.version = cmd.version;
+        for (field, v) in cmd.values() {
+            match self.channels.iter().find(|c| c.command_field == field) {
+                Some(c) => {
+                    self.status.set(&c.status_field, v);
+                    if let Some(active) = &c.active_field {
+                        self.status.set(active, v);
+                    }
+                }
+                None => log::warn!("Ignoring unknown command field {}", field),
+            }
+        }
+        self.apply_faults();
+
+        if self.faults.contains(&Fault::NoReply) {
+            None
+        } else {
+            Some(self.response(None))
+        }
+    }
+
+    /// Payload reporting the current status, with an optional message.
+    pub(crate) fn response(&self, message: Option<String>) -> Vec<u8> {
+        if self.faults.contains(&Fault::Garbage) {
+            return b"{\"status\": {\"tx_power_enabled\": tru".to_vec();
+        }
+
+        self.encoding
+            .encode(&Response {
+                version: self.version,
+                status: self.status.clone(),
+                message,
+                timestamp: Local::now(),
+            })
+            .expect("response should serialise")
+    }
+
+    fn apply_faults(&mut self) {
+        if self.faults.contains(&Fault::StuckPtt) {
+            if let Some(c) = self.channels.iter().find(|c| c.name == "ptt") {
+                self.status.set(&c.status_field, true);
+                if let Some(active) = &c.active_field {
+                    self.status.set(active, true);
+                }
+            }
+        }
+    }
+}
+
+/// Runs a simulated station against the configured broker until interrupted.
+pub(crate) async fn run(mut config: LinkConfig, faults: Vec<Fault>) -> Result<()> {
+    if config.station.transport != TransportKind::Mqtt {
+        return Err(anyhow!(
+            "Only stations using the MQTT transport can be simulated"
+        ));
+    }
+
+    let mut station = Station::new(config.channels.clone(), faults);
+    station.encoding = config.station.encoding;
+
+    // Availability is that of the bot, not of the simulated station
+    config.mqtt.availability_topic = None;
+
+    // The simulated station only ever uses the preferred broker
+    let broker_uri = &config.mqtt.brokers[0];
+    let mqtt_client = broker::create_client(
+        &config.mqtt,
+        broker_uri,
+        &format!("{}-simulate", config.mqtt.client_id),
+    )?;
+    mqtt_client.subscribe(
+        mqtt::SubscriptionBuilder::default()
+            .topic(config.station.command_topic.clone())
+            .build()
+            .unwrap(),
+    );
+    let mut mqtt_rx = mqtt_client.rx_channel();
+    broker::start(&mqtt_client, &config.mqtt, broker_uri).await?;
+
+    // Plays the station's part in signing: commands must be signed and status is signed
+    let signer = config.signing.as_ref().map(Signer::new);
+
+    // Publishes status, or a reply to a command if given correlation data
+    let publish = |topic: &str, payload: Vec<u8>, correlation_data: Option<Vec<u8>>| {
+        let payload = match &signer {
+            Some(signer) => match std::str::from_utf8(&payload)
+                .map_err(Into::into)
+                .and_then(|payload| signer.seal(payload))
+            {
+                Ok(sealed) => sealed.into_bytes(),
+                Err(e) => {
+                    log::error!("Failed to sign status because {}", e);
+                    return;
+                }
+            },
+            None => payload,
+        };
+        let mut message = mqtt::paho_mqtt::MessageBuilder::new()
+            .topic(topic)
+            .payload(payload)
+            .qos(2);
+        if let Some(data) = correlation_data {
+            let mut properties = mqtt::paho_mqtt::Properties::new();
+            if let Err(e) =
+                properties.push_binary(mqtt::paho_mqtt::PropertyCode::CorrelationData, data)
+            {
+                log::error!("Failed to set correlation data because {}", e);
+                return;
+            }
+            message = message.properties(properties);
+        }
+        if let Err(e) = mqtt_client.send(message.finalize()) {
+            log::error!("Failed to publish status because {}", e);
+        }
+    };
+
+    log::info!("Simulating {}", config.station.name);
+    publish(
+        &config.station.status_topic,
+        station.response(Some("Simulated station started".to_string())),
+        None,
+    );
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                return Ok(());
+            }
+            event = mqtt_rx.recv() => match event {
+                Ok(mqtt::Event::Rx(msg)) => match open_command(signer.as_ref(), msg.payload(), config.station.encoding) {
+                    Ok(cmd) => {
+                        log::info!("Received command: {:?}", cmd);
+                        if let Some(payload) = station.handle(&cmd) {
+                            // MQTT v5 commands ask for a reply, which is sent as well as the status
+                            let properties = msg.properties();
+                            if let (Some(topic), Some(data)) = (
+                                properties.get_string(mqtt::paho_mqtt::PropertyCode::ResponseTopic),
+                                properties.get_binary(mqtt::paho_mqtt::PropertyCode::CorrelationData),
+                            ) {
+                                publish(&topic, payload.clone(), Some(data));
+                            }
+                            publish(&config.station.status_topic, payload, None);
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Rejected command from MQTT message, because {:#}", e);
+                    }
+                },
+                Ok(_) => {}
+                Err(e) => return Err(anyhow!("MQTT receive failed ({})", e)),
+            },
+        }
+    }
+}
+
+fn open_command(signer: Option<&Signer>, payload: &[u8], expected: Encoding) -> Result<Command> {
+    match signer {
+        Some(signer) => encoding::decode(
+            signer.open(std::str::from_utf8(payload)?)?.as_bytes(),
+            expected,
+        ),
+        None => encoding::decode(payload, expected),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{channel, config::default_channels};
+
+    fn response(payload: Option<Vec<u8>>) -> Status {
+        let payload = payload.expect("station should reply");
+        Response::parse(&payload, Encoding::Json).unwrap().status
+    }
+
+    fn command(op: &str) -> Command {
+        channel::station_command(&default_channels(), &op.parse().unwrap())
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn applies_commands() {
+        let mut station = Station::new(default_channels(), Vec::new());
+        let cmd = command("shutdown").with_version(2);
+        let payload = station.handle(&cmd);
+        assert_eq!(
+            Response::parse(payload.as_deref().unwrap(), Encoding::Json)
+                .unwrap()
+                .version,
+            Some(2)
+        );
+        let status = response(payload);
+        assert!(channel::is_satisfied_by(&default_channels(), &cmd, &status));
+        assert_eq!(status.get("tx_power_active"), Some(false));
+    }
+
+    #[test]
+    fn faults() {
+        let cmd = command("ptt disable");
+
+        let mut station = Station::new(default_channels(), vec![Fault::StuckPtt]);
+        let status = response(station.handle(&cmd));
+        assert!(!channel::is_satisfied_by(
+            &default_channels(),
+            &cmd,
+            &status
+        ));
+        assert_eq!(status.get("ptt_active"), Some(true));
+
+        let mut station = Station::new(default_channels(), vec![Fault::NoReply]);
+        assert_eq!(station.handle(&cmd), None);
+
+        let mut station = Station::new(default_channels(), vec![Fault::Garbage]);
+        assert!(Response::parse(&station.handle(&cmd).unwrap(), Encoding::Json).is_err());
+
+        let mut station = Station::new(default_channels(), Vec::new());
+        station.encoding = Encoding::Cbor;
+        let payload = station.handle(&cmd).unwrap();
+        assert_eq!(Encoding::detect(&payload), Some(Encoding::Cbor));
+        assert_eq!(response(Some(payload)).get("ptt_enabled"), Some(false));
+    }
+}
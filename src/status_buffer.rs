@@ -0,0 +1,246 @@
+use crate::{channel, config::ChannelConfig, schema::Status};
+use chrono::{offset::Local, DateTime};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, path::PathBuf};
+
+/// A status update that could not be posted to Matrix at the time it was received.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub(crate) struct BufferedStatus {
+    pub timestamp: DateTime<Local>,
+    pub status: Status,
+    pub message: Option<String>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct Contents {
+    baseline: Option<Status>,
+    entries: VecDeque<BufferedStatus>,
+    dropped: usize,
+    /// Number of discarded updates that changed the state of a channel
+    #[serde(default)]
+    dropped_changes: usize,
+}
+
+/// Bounded, disk backed store of status updates received while Matrix is unreachable.
+pub(crate) struct StatusBuffer {
+    /// Where the buffer is saved, if anywhere
+    path: Option<PathBuf>,
+    capacity: usize,
+    contents: Contents,
+}
+
+impl StatusBuffer {
+    /// Loads the buffer saved at `path`, starting afresh if there is none or it cannot be read.
+    pub(crate) fn load(path: PathBuf, capacity: usize) -> Self {
+        let contents = if path.exists() {
+            match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|s| Ok(serde_json::from_str(&s)?))
+            {
+                Ok(contents) => contents,
+                Err(e) => {
+                    log::warn!(
+                        "Discarding unreadable status buffer {} ({})",
+                        path.display(),
+                        e
+                    );
+                    Contents::default()
+                }
+            }
+        } else {
+            Contents::default()
+        };
+
+        Self {
+            path: Some(path),
+            capacity,
+            contents,
+        }
+    }
+
+    /// A buffer that is kept in memory only.
+    pub(crate) fn in_memory(capacity: usize) -> Self {
+        Self {
+            path: None,
+            capacity,
+            contents: Contents::default(),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.contents.entries.is_empty()
+    }
+
+    /// Adds a status update to the buffer, discarding the oldest update if the buffer is full.
+    ///
+    /// `previous` is the last status that was successfully posted, it is used as the starting
+    /// point of the summary if this is the first update to be buffered.
+    pub(crate) fn push(
+        &mut self,
+        channels: &[ChannelConfig],
+        previous: &Status,
+        entry: BufferedStatus,
+    ) {
+        if self.contents.entries.is_empty() {
+            self.contents.baseline = Some(previous.clone());
+        }
+
+        // A capacity of 0 is rejected when checking the configuration
+        while self.capacity > 0 && self.contents.entries.len() >= self.capacity {
+            if let Some(old) = self.contents.entries.pop_front() {
+                let baseline = self.contents.baseline.take().unwrap_or_default();
+                if channel::state_changed(channels, &baseline, &old.status) {
+                    self.contents.dropped_changes += 1;
+                }
+                self.contents.baseline = Some(old.status);
+                self.contents.dropped += 1;
+            }
+        }
+        self.contents.entries.push_back(entry);
+
+        self.save();
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.contents = Contents::default();
+        self.save();
+    }
+
+    /// Produces a single message describing everything that happened while updates were being
+    /// buffered.
+    pub(crate) fn summary(&self, station_name: &str, channels: &[ChannelConfig]) -> String {
+        let mut previous = self.contents.baseline.clone().unwrap_or_default();
+        let mut status_changes = self.contents.dropped_changes;
+        for entry in &self.contents.entries {
+            if channel::state_changed(channels, &previous, &entry.status) {
+                status_changes += 1;
+            }
+            previous = entry.status.clone();
+        }
+        let messages = self
+            .contents
+            .entries
+            .iter()
+            .filter(|e| e.message.is_some())
+            .count();
+
+        let mut summary = format!(
+            "**{}** while disconnected: {} status change{}",
+            station_name,
+            status_changes,
+            if status_changes == 1 { "" } else { "s" },
+        );
+        if messages > 0 {
+            summary.push_str(&format!(
+                ", {} message{}",
+                messages,
+                if messages == 1 { "" } else { "s" },
+            ));
+        }
+        if self.contents.dropped > 0 {
+            summary.push_str(&format!(" ({} oldest not retained)", self.contents.dropped));
+        }
+
+        let mut previous = self.contents.baseline.clone().unwrap_or_default();
+        for entry in &self.contents.entries {
+            for change in channel::describe_changes(channels, &previous, &entry.status) {
+                summary.push_str(&format!(
+                    "<br>{} at {} by station",
+                    change,
+                    entry.timestamp.format("%H:%M")
+                ));
+            }
+            if let Some(m) = &entry.message {
+                summary.push_str(&format!(
+                    "<br>Message at {}: {}",
+                    entry.timestamp.format("%H:%M"),
+                    m
+                ));
+            }
+            previous = entry.status.clone();
+        }
+
+        summary
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string(&self.contents)
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(std::fs::write(path, s)?));
+
+        if let Err(e) = result {
+            log::error!(
+                "Failed to write status buffer to {} ({})",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::default_channels;
+    use chrono::TimeZone;
+
+    fn buffer(capacity: usize) -> StatusBuffer {
+        StatusBuffer::in_memory(capacity)
+    }
+
+    fn message(hour: u32, tx_power_active: bool) -> BufferedStatus {
+        BufferedStatus {
+            message: Some("Hello".to_string()),
+            ..entry(hour, tx_power_active)
+        }
+    }
+
+    fn entry(hour: u32, tx_power_active: bool) -> BufferedStatus {
+        BufferedStatus {
+            timestamp: Local.with_ymd_and_hms(2024, 1, 1, hour, 12, 0).unwrap(),
+            status: Status([("tx_power_active".to_string(), tx_power_active.into())].into()),
+            message: None,
+        }
+    }
+
+    #[test]
+    fn buffer_is_bounded() {
+        let channels = default_channels();
+        let mut buffer = buffer(2);
+        buffer.push(&channels, &Status::default(), entry(1, true));
+        buffer.push(&channels, &Status::default(), entry(2, false));
+        buffer.push(&channels, &Status::default(), entry(3, true));
+
+        assert_eq!(buffer.contents.entries.len(), 2);
+        assert_eq!(buffer.contents.dropped, 1);
+        assert_eq!(buffer.contents.dropped_changes, 1);
+        assert_eq!(buffer.contents.baseline, Some(entry(1, true).status));
+    }
+
+    #[test]
+    fn summary() {
+        let channels = default_channels();
+        let mut buffer = buffer(10);
+        buffer.push(&channels, &entry(0, true).status, entry(3, false));
+        buffer.push(&channels, &entry(0, true).status, message(4, false));
+
+        assert_eq!(
+            buffer.summary("mb7pmf", &channels),
+            "**mb7pmf** while disconnected: 1 status change, 1 message<br>TX Power changed to OFF at 03:12 by station<br>Message at 04:12: Hello"
+        );
+    }
+
+    #[test]
+    fn load_corrupt() {
+        let path = std::env::temp_dir().join(format!(
+            "matrix-remote-closedown-status-buffer-corrupt-{}.json",
+            std::process::id()
+        ));
+        std::fs::write(&path, "{\"entries\": [").unwrap();
+        let buffer = StatusBuffer::load(path, 10);
+        assert!(buffer.is_empty());
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+/// A message template with `{name}` placeholders, literal braces are written as `{{` and `}}`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(transparent)]
+pub(crate) struct Template(String);
+
+impl Template {
+    pub(crate) fn new(template: &str) -> Self {
+        Self(template.to_string())
+    }
+
+    pub(crate) fn render(&self, values: &[(&str, &str)]) -> Result<String> {
+        let mut out = String::new();
+        let mut chars = self.0.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.next_if_eq(&'{').is_some() => out.push('{'),
+                '}' if chars.next_if_eq(&'}').is_some() => out.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(anyhow!("Unterminated placeholder \"{{{}\"", name)),
+                        }
+                    }
+                    let value = values
+                        .iter()
+                        .find(|(k, _)| *k == name.trim())
+                        .ok_or_else(|| anyhow!("Unknown placeholder \"{{{}}}\"", name))?
+                        .1;
+                    out.push_str(value);
+                }
+                '}' => return Err(anyhow!("Unmatched \"}}\"")),
+                c => out.push(c),
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Renders the template, or `fallback` if it cannot be rendered (e.g. a reloaded template with
+    /// an unknown placeholder), so that the message is still posted.
+    pub(crate) fn render_or(&self, fallback: &Template, values: &[(&str, &str)]) -> String {
+        self.render(values).unwrap_or_else(|e| {
+            log::error!(
+                "Failed to render template \"{}\", using \"{}\" ({})",
+                self.0,
+                fallback.0,
+                e
+            );
+            fallback.render(values).unwrap_or_else(|e| e.to_string())
+        })
+    }
+
+    /// Checks that the template renders when given the named placeholders.
+    pub(crate) fn validate(&self, names: &[&str]) -> Result<()> {
+        let values: Vec<(&str, &str)> = names.iter().map(|n| (*n, "")).collect();
+        self.render(&values).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_ok() {
+        assert_eq!(
+            Template::new("**{station}** at { timestamp }")
+                .render(&[("station", "mb7pmf"), ("timestamp", "now")])
+                .unwrap(),
+            "**mb7pmf** at now"
+        );
+    }
+
+    #[test]
+    fn render_escapes() {
+        assert_eq!(
+            Template::new("{{{station}}}")
+                .render(&[("station", "mb7pmf")])
+                .unwrap(),
+            "{mb7pmf}"
+        );
+    }
+
+    #[test]
+    fn render_err() {
+        assert!(Template::new("{nope}").render(&[("station", "")]).is_err());
+        assert!(Template::new("{station")
+            .render(&[("station", "")])
+            .is_err());
+        assert!(Template::new("station}")
+            .render(&[("station", "")])
+            .is_err());
+    }
+
+    #[test]
+    fn render_fallback() {
+        let values = [("station", "mb7pmf")];
+        assert_eq!(
+            Template::new("{nope}").render_or(&Template::new("**{station}**"), &values),
+            "**mb7pmf**"
+        );
+        assert_eq!(
+            Template::new("{station}").render_or(&Template::new("{nope}"), &values),
+            "mb7pmf"
+        );
+    }
+}
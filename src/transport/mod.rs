@@ -0,0 +1,170 @@
+pub(crate) mod mqtt;
+pub(crate) mod serial;
+
+use crate::{
+    config::{LinkConfig, TransportKind},
+    event::{CommandReplyEvent, Event, RelayMessageEvent},
+    metrics::REJECTED_MESSAGES,
+    signing::Signer,
+};
+use anyhow::{anyhow, Result};
+use kagiyama::prometheus::registry::Registry;
+use rand::RngCore;
+use std::sync::Arc;
+use tokio::sync::broadcast::Sender;
+
+/// A way of communicating with the station.
+///
+/// Status messages received from the station are sent as `Event::StatusMessageReceived`, replies
+/// to specific commands (if supported) as `Event::CommandReplyReceived`.
+pub(crate) trait Transport: Send + Sync {
+    /// Sends a (serialised) command message to the station, any reply to it will carry
+    /// `correlation_id`.
+    fn send_command(&self, payload: Vec<u8>, correlation_id: &str) -> Result<()>;
+
+    /// Sends a message to a topic other than the command topic (e.g. for custom operations).
+    fn publish(&self, _topic: &str, _payload: Vec<u8>) -> Result<()> {
+        Err(anyhow!("This transport cannot send to other topics"))
+    }
+
+    /// Whether the station replies to each command (i.e. `Event::CommandReplyReceived` is sent).
+    fn correlates_replies(&self) -> bool {
+        false
+    }
+
+    /// Called before the bot exits.
+    fn shutdown(&self) {}
+
+    fn register_metrics(&self, _registry: &mut Registry) {}
+}
+
+/// Connects to the station using the configured transport.
+///
+/// If signing is configured, commands are sent in signed envelopes and status messages that are
+/// not correctly signed are discarded.
+pub(crate) async fn connect(
+    config: &LinkConfig,
+    client_id: &str,
+    tx: Sender<Event>,
+) -> Result<Box<dyn Transport>> {
+    let signer = config.signing.as_ref().map(|s| Arc::new(Signer::new(s)));
+    let receiver = Receiver {
+        tx,
+        signer: signer.clone(),
+    };
+
+    let transport: Box<dyn Transport> = match config.station.transport {
+        TransportKind::Mqtt => {
+            Box::new(mqtt::MqttTransport::connect(config, client_id, receiver).await?)
+        }
+        TransportKind::Serial => Box::new(serial::SerialTransport::start(
+            config
+                .serial
+                .as_ref()
+                .ok_or_else(|| anyhow!("No serial port configured"))?,
+            receiver,
+        )),
+    };
+
+    Ok(match signer {
+        Some(signer) => Box::new(SignedTransport {
+            inner: transport,
+            signer,
+        }),
+        None => transport,
+    })
+}
+
+/// Passes messages received from the station on as events, verifying their signatures if
+/// signing is configured.
+#[derive(Clone)]
+pub(crate) struct Receiver {
+    tx: Sender<Event>,
+    signer: Option<Arc<Signer>>,
+}
+
+impl Receiver {
+    pub(crate) fn deliver(&self, payload: Vec<u8>) {
+        if let Some(payload) = self.open(payload) {
+            crate::send_event!(self.tx, Event::StatusMessageReceived(payload));
+        }
+    }
+
+    pub(crate) fn deliver_reply(&self, correlation_id: String, payload: Vec<u8>) {
+        if let Some(payload) = self.open(payload) {
+            crate::send_event!(
+                self.tx,
+                Event::CommandReplyReceived(CommandReplyEvent {
+                    correlation_id,
+                    payload,
+                })
+            );
+        }
+    }
+
+    /// Passes on a message received on a relayed topic, these are not expected to be signed.
+    pub(crate) fn relay(&self, topic: String, payload: String) {
+        crate::send_event!(
+            self.tx,
+            Event::RelayMessageReceived(RelayMessageEvent { topic, payload })
+        );
+    }
+
+    /// Sends an event that does not come from the station itself (e.g. about the connection).
+    pub(crate) fn notify(&self, event: Event) {
+        crate::send_event!(self.tx, event);
+    }
+
+    fn open(&self, payload: Vec<u8>) -> Option<Vec<u8>> {
+        match &self.signer {
+            Some(signer) => match std::str::from_utf8(&payload)
+                .map_err(Into::into)
+                .and_then(|message| signer.open(message))
+            {
+                Ok(payload) => Some(payload.into_bytes()),
+                Err(e) => {
+                    log::warn!("Rejected message from station ({:#})", e);
+                    REJECTED_MESSAGES.inc();
+                    None
+                }
+            },
+            None => Some(payload),
+        }
+    }
+}
+
+struct SignedTransport {
+    inner: Box<dyn Transport>,
+    signer: Arc<Signer>,
+}
+
+impl Transport for SignedTransport {
+    fn send_command(&self, payload: Vec<u8>, correlation_id: &str) -> Result<()> {
+        let sealed = self.signer.seal(std::str::from_utf8(&payload)?)?;
+        self.inner.send_command(sealed.into_bytes(), correlation_id)
+    }
+
+    fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        let sealed = self.signer.seal(std::str::from_utf8(&payload)?)?;
+        self.inner.publish(topic, sealed.into_bytes())
+    }
+
+    fn correlates_replies(&self) -> bool {
+        self.inner.correlates_replies()
+    }
+
+    fn shutdown(&self) {
+        self.inner.shutdown();
+    }
+
+    fn register_metrics(&self, registry: &mut Registry) {
+        self.inner.register_metrics(registry);
+    }
+}
+
+/// Generates an identifier for matching replies to a command.
+pub(crate) fn new_correlation_id() -> String {
+    let mut id = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut id);
+    hex::encode(id)
+}
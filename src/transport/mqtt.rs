@@ -0,0 +1,391 @@
+use super::{Receiver, Transport};
+use crate::{
+    broker,
+    config::LinkConfig,
+    connectivity::Service,
+    encoding,
+    event::{BrokerChangedEvent, ConnectivityEvent, Event},
+    metrics::{BrokerLabels, ACTIVE_BROKER, BROKER_SWITCHES},
+    relay::topic_matches,
+};
+use anyhow::{anyhow, Result};
+use kagiyama::prometheus::registry::Registry;
+use mqtt_channel_client as mqtt;
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    task::JoinHandle,
+};
+
+/// How often the connection is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How long the active broker may be unreachable before switching to another
+const FAILOVER_DELAY: Duration = Duration::from_secs(30);
+
+/// How often more preferred brokers are tried while using a fallback broker
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum time to wait when connecting to a broker
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Talks to the station via the first reachable broker in the configured list, switching to
+/// another broker if it becomes unreachable and back again once a more preferred broker returns.
+///
+/// With MQTT v5 each command asks for a reply on the response topic, tagged with correlation
+/// data so that it can be matched to the command.
+pub(crate) struct MqttTransport {
+    client: Arc<RwLock<mqtt::Client>>,
+    command_topic: String,
+    response_topic: Option<String>,
+    availability_topic: Option<String>,
+    single_broker: bool,
+}
+
+impl MqttTransport {
+    pub(crate) async fn connect(
+        config: &LinkConfig,
+        client_id: &str,
+        receiver: Receiver,
+    ) -> Result<Self> {
+        let mut errors = Vec::new();
+        let mut connected = None;
+        for i in 0..config.mqtt.brokers.len() {
+            match connect_broker(config, i, client_id).await {
+                Ok(c) => {
+                    connected = Some((i, c));
+                    break;
+                }
+                Err(e) => {
+                    log::warn!("{:#}", e);
+                    errors.push(format!("{:#}", e));
+                }
+            }
+        }
+        let (active, (client, rx)) =
+            connected.ok_or_else(|| anyhow!("No MQTT broker reachable: {}", errors.join("; ")))?;
+
+        log::info!("Connected to MQTT broker {}", config.mqtt.brokers[active]);
+        announce_availability(config, &client);
+        ACTIVE_BROKER
+            .get_or_create(&BrokerLabels::new(&config.mqtt.brokers[active]))
+            .set(1);
+
+        let client = Arc::new(RwLock::new(client));
+        tokio::spawn(supervise(
+            client.clone(),
+            rx,
+            active,
+            config.clone(),
+            client_id.to_string(),
+            receiver,
+        ));
+
+        Ok(Self {
+            client,
+            command_topic: config.station.command_topic.clone(),
+            response_topic: response_topic(config).map(str::to_string),
+            availability_topic: config.mqtt.availability_topic.clone(),
+            single_broker: config.mqtt.brokers.len() == 1,
+        })
+    }
+}
+
+impl Transport for MqttTransport {
+    fn send_command(&self, payload: Vec<u8>, correlation_id: &str) -> Result<()> {
+        let message = broker::command_message(
+            &self.command_topic,
+            payload,
+            self.response_topic
+                .as_deref()
+                .map(|topic| (topic, correlation_id)),
+        )?;
+        Ok(self.client.read().unwrap().send(message)?)
+    }
+
+    fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<()> {
+        let message = broker::command_message(topic, payload, None)?;
+        Ok(self.client.read().unwrap().send(message)?)
+    }
+
+    fn correlates_replies(&self) -> bool {
+        self.response_topic.is_some()
+    }
+
+    fn shutdown(&self) {
+        // A clean disconnect does not trigger the last will, so say goodbye explicitly
+        if let Some(topic) = &self.availability_topic {
+            if let Err(e) = self
+                .client
+                .read()
+                .unwrap()
+                .send(broker::availability_message(topic, false))
+            {
+                log::warn!("Failed to publish availability ({})", e);
+            }
+        }
+    }
+
+    fn register_metrics(&self, registry: &mut Registry) {
+        // Client metrics can only be registered once, they would stop updating after a switch
+        if self.single_broker {
+            self.client.read().unwrap().register_metrics(registry);
+        } else {
+            log::info!("Several MQTT brokers are configured, MQTT client metrics are not exported");
+        }
+    }
+}
+
+/// A client connected to a broker and the receiver of its events.
+type Connection = (mqtt::Client, broadcast::Receiver<mqtt::Event>);
+
+async fn connect_broker(config: &LinkConfig, index: usize, client_id: &str) -> Result<Connection> {
+    let broker_uri = &config.mqtt.brokers[index];
+    let client = broker::create_client(&config.mqtt, broker_uri, client_id)?;
+
+    client.subscribe(
+        mqtt::SubscriptionBuilder::default()
+            .topic(config.station.status_topic.clone())
+            .build()
+            .unwrap(),
+    );
+    for topic in &config.relay_topics {
+        client.subscribe(
+            mqtt::SubscriptionBuilder::default()
+                .topic(topic.clone())
+                .build()
+                .unwrap(),
+        );
+    }
+    if let Some(topic) = response_topic(config) {
+        client.subscribe(
+            mqtt::SubscriptionBuilder::default()
+                .topic(topic.to_string())
+                .build()
+                .unwrap(),
+        );
+    }
+    let rx = client.rx_channel();
+
+    tokio::time::timeout(
+        CONNECT_TIMEOUT,
+        broker::start(&client, &config.mqtt, broker_uri),
+    )
+    .await
+    .map_err(|_| anyhow!("Timed out connecting to MQTT broker {}", broker_uri))??;
+
+    Ok((client, rx))
+}
+
+/// Checks that a broker can be connected to, using another client ID and no last will so that
+/// neither the active connection nor the bot's availability are disturbed.
+async fn check_broker(config: &LinkConfig, index: usize, client_id: &str) -> Result<()> {
+    let broker_uri = &config.mqtt.brokers[index];
+    let mut mqtt_config = config.mqtt.clone();
+    mqtt_config.availability_topic = None;
+    let client = broker::create_client(&mqtt_config, broker_uri, &format!("{}-probe", client_id))?;
+
+    tokio::time::timeout(
+        CONNECT_TIMEOUT,
+        broker::start(&client, &mqtt_config, broker_uri),
+    )
+    .await
+    .map_err(|_| anyhow!("Timed out connecting to MQTT broker {}", broker_uri))??;
+
+    Ok(())
+}
+
+fn announce_availability(config: &LinkConfig, client: &mqtt::Client) {
+    if let Some(topic) = &config.mqtt.availability_topic {
+        if let Err(e) = client.send(broker::availability_message(topic, true)) {
+            log::warn!("Failed to publish availability ({})", e);
+        }
+    }
+}
+
+/// The topic replies to commands are received on, if the broker supports them.
+fn response_topic(config: &LinkConfig) -> Option<&str> {
+    (config.mqtt.version == 5).then_some(config.station.response_topic.as_str())
+}
+
+fn handle_message(config: &LinkConfig, receiver: &Receiver, msg: mqtt::paho_mqtt::Message) {
+    let topic = msg.topic();
+    let payload = msg.payload().to_vec();
+
+    if response_topic(config) == Some(topic) {
+        match msg
+            .properties()
+            .get_binary(mqtt::paho_mqtt::PropertyCode::CorrelationData)
+        {
+            Some(id) => receiver.deliver_reply(String::from_utf8_lossy(&id).to_string(), payload),
+            None => log::warn!(
+                "Ignoring reply without correlation data: {}",
+                encoding::describe(&payload)
+            ),
+        }
+        return;
+    }
+
+    // A relayed topic may overlap with the status topic, in which case the message is both
+    if config
+        .relay_topics
+        .iter()
+        .any(|filter| topic_matches(filter, topic))
+    {
+        receiver.relay(topic.to_string(), msg.payload_str().to_string());
+    }
+    if topic_matches(&config.station.status_topic, topic) {
+        receiver.deliver(payload);
+    }
+}
+
+/// A broker found to be usable while the active one was still being served.
+#[derive(Clone, Copy)]
+struct Probe {
+    target: usize,
+    failing_over: bool,
+}
+
+/// Tries each candidate broker in turn, returning the first that is usable.
+async fn probe(
+    config: LinkConfig,
+    client_id: String,
+    candidates: Vec<usize>,
+    failing_over: bool,
+) -> Option<Probe> {
+    for target in candidates {
+        match check_broker(&config, target, &client_id).await {
+            Ok(()) => {
+                return Some(Probe {
+                    target,
+                    failing_over,
+                })
+            }
+            Err(e) => log::debug!(
+                "Broker {} not usable ({:#})",
+                config.mqtt.brokers[target],
+                e
+            ),
+        }
+    }
+    None
+}
+
+/// Connects the bot to the broker a probe found, with its own client ID and last will (clustered
+/// brokers may end the session with the old broker, which is dropped once this succeeds anyway).
+async fn switch_to(
+    config: LinkConfig,
+    client_id: String,
+    probe: Probe,
+) -> Result<(Probe, Connection)> {
+    Ok((
+        probe,
+        connect_broker(&config, probe.target, &client_id).await?,
+    ))
+}
+
+async fn supervise(
+    client: Arc<RwLock<mqtt::Client>>,
+    mut rx: broadcast::Receiver<mqtt::Event>,
+    mut active: usize,
+    config: LinkConfig,
+    client_id: String,
+    receiver: Receiver,
+) {
+    let brokers = &config.mqtt.brokers;
+    let mut disconnected_since: Option<Instant> = None;
+    let mut last_probe = Instant::now();
+    let mut check = tokio::time::interval(CHECK_INTERVAL);
+    // Probes and switches run separately so that messages from the active broker keep being handled
+    let mut probing: Option<JoinHandle<Option<Probe>>> = None;
+    let mut switching: Option<JoinHandle<Result<(Probe, Connection)>>> = None;
+
+    loop {
+        tokio::select! {
+            event = rx.recv() => match event {
+                Ok(mqtt::Event::Rx(msg)) => {
+                    handle_message(&config, &receiver, msg);
+                }
+                Ok(mqtt::Event::Status(mqtt::StatusEvent::Connected)) => {
+                    disconnected_since = None;
+                    announce_availability(&config, &client.read().unwrap());
+                    receiver.notify(Event::ConnectivityChanged(ConnectivityEvent::now(Service::Mqtt, true)));
+                }
+                Ok(mqtt::Event::Status(mqtt::StatusEvent::Disconnected)) => {
+                    disconnected_since.get_or_insert_with(Instant::now);
+                    receiver.notify(Event::ConnectivityChanged(ConnectivityEvent::now(Service::Mqtt, false)));
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            },
+            _ = check.tick(), if probing.is_none() && switching.is_none() => {
+                let failing_over = disconnected_since.is_some_and(|t| t.elapsed() >= FAILOVER_DELAY);
+                let candidates: Vec<usize> = if failing_over {
+                    (0..brokers.len()).filter(|i| *i != active).collect()
+                } else if active != 0 && last_probe.elapsed() >= PROBE_INTERVAL {
+                    last_probe = Instant::now();
+                    (0..active).collect()
+                } else {
+                    continue;
+                };
+
+                probing = Some(tokio::spawn(probe(config.clone(), client_id.clone(), candidates, failing_over)));
+            },
+            result = async { probing.as_mut().unwrap().await }, if probing.is_some() => {
+                probing = None;
+                let probe = match result {
+                    Ok(Some(probe)) => probe,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::error!("MQTT broker probe failed ({})", e);
+                        continue;
+                    }
+                };
+                if probe.failing_over && disconnected_since.is_none() {
+                    log::debug!("Active MQTT broker {} returned, not switching", brokers[active]);
+                    continue;
+                }
+                switching = Some(tokio::spawn(switch_to(config.clone(), client_id.clone(), probe)));
+            },
+            result = async { switching.as_mut().unwrap().await }, if switching.is_some() => {
+                switching = None;
+                let (probe, (new_client, new_rx)) = match result {
+                    Ok(Ok(connection)) => connection,
+                    Ok(Err(e)) => {
+                        log::warn!("Not switching MQTT broker ({:#})", e);
+                        continue;
+                    }
+                    Err(e) => {
+                        log::error!("MQTT broker switch failed ({})", e);
+                        continue;
+                    }
+                };
+                let target = probe.target;
+                log::warn!("Switching MQTT broker from {} to {}", brokers[active], brokers[target]);
+
+                // Dropping the old client disconnects it
+                *client.write().unwrap() = new_client;
+                rx = new_rx;
+                announce_availability(&config, &client.read().unwrap());
+
+                ACTIVE_BROKER.get_or_create(&BrokerLabels::new(&brokers[active])).set(0);
+                ACTIVE_BROKER.get_or_create(&BrokerLabels::new(&brokers[target])).set(1);
+                BROKER_SWITCHES.inc();
+                receiver.notify(Event::BrokerChanged(BrokerChangedEvent {
+                    from: brokers[active].clone(),
+                    to: brokers[target].clone(),
+                    returned: !probe.failing_over,
+                }));
+
+                receiver.notify(Event::ConnectivityChanged(ConnectivityEvent::now(Service::Mqtt, true)));
+
+                active = target;
+                disconnected_since = None;
+                last_probe = Instant::now();
+            },
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use super::{Receiver, Transport};
+use crate::config::SerialConfig;
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    sync::mpsc,
+};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+/// Time to wait before trying to reopen a serial port that failed
+const REOPEN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// For remote-closedown hardware connected directly to the bot host, exchanging the same JSON
+/// messages as over MQTT, one per line.
+pub(crate) struct SerialTransport {
+    commands: mpsc::UnboundedSender<String>,
+}
+
+impl SerialTransport {
+    pub(crate) fn start(config: &SerialConfig, receiver: Receiver) -> Self {
+        let (commands, mut commands_rx) = mpsc::unbounded_channel();
+        let config = config.clone();
+
+        tokio::spawn(async move {
+            let mut reopening = false;
+            loop {
+                match tokio_serial::new(&config.port, config.baud_rate).open_native_async() {
+                    Ok(port) => {
+                        log::info!("Opened serial port {}", config.port);
+
+                        // Commands requested while the port was unavailable are stale by now
+                        while reopening && commands_rx.try_recv().is_ok() {
+                            log::warn!("Discarding command sent while serial port was unavailable");
+                        }
+
+                        match run_port(port, &mut commands_rx, &receiver).await {
+                            Ok(()) => return,
+                            Err(e) => log::warn!("Serial port {} failed ({})", config.port, e),
+                        }
+                    }
+                    Err(e) => {
+                        log::warn!("Failed to open serial port {} ({})", config.port, e);
+                    }
+                }
+                reopening = true;
+                tokio::time::sleep(REOPEN_INTERVAL).await;
+            }
+        });
+
+        Self { commands }
+    }
+}
+
+impl Transport for SerialTransport {
+    fn send_command(&self, payload: Vec<u8>, _correlation_id: &str) -> Result<()> {
+        Ok(self.commands.send(String::from_utf8(payload)?)?)
+    }
+}
+
+/// Exchanges messages over an open port, returning `Ok` only once the transport is dropped.
+async fn run_port(
+    port: SerialStream,
+    commands: &mut mpsc::UnboundedReceiver<String>,
+    receiver: &Receiver,
+) -> Result<()> {
+    let (reader, mut writer) = tokio::io::split(port);
+    let mut lines = BufReader::new(reader).lines();
+
+    loop {
+        tokio::select! {
+            line = lines.next_line() => match line? {
+                Some(line) => {
+                    if !line.trim().is_empty() {
+                        receiver.deliver(line.into_bytes());
+                    }
+                }
+                None => return Err(anyhow!("port closed")),
+            },
+            cmd = commands.recv() => match cmd {
+                Some(cmd) => {
+                    writer.write_all(cmd.as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                    writer.flush().await?;
+                }
+                None => return Ok(()),
+            },
+        }
+    }
+}